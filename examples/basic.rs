@@ -3,6 +3,7 @@ extern crate starstraw;
 use axum::Router;
 use starstraw::*;
 use std::env::var;
+use std::net::SocketAddr;
 
 #[tokio::main]
 async fn main() {
@@ -14,8 +15,16 @@ async fn main() {
     };
 
     // create database
-    let database = Database::new(Database::env_options(), ServerOptions::truthy()).await;
-    database.init().await;
+    let database = Database::new(
+        Database::env_options(),
+        ServerOptions {
+            owner: Database::env_owner(),
+            ..ServerOptions::truthy()
+        },
+    )
+    .await;
+    database.init().await.unwrap();
+    database.spawn_key_rotation();
 
     // create app
     let app = Router::new()
@@ -27,5 +36,10 @@ async fn main() {
         .unwrap();
 
     println!("Starting server at http://localhost:{port}!");
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
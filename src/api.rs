@@ -1,19 +1,21 @@
 //! Responds to API requests
 use crate::database::Database;
+use crate::extract::{request_token, AuthProfile, ClientInfo, OptionalAuthProfile};
 use crate::model::{
-    GrantSkill, GrantTitle, ProfileCreate, ProfileLogin, RevokeSkill, SkillManager, SkillName,
-    StrawError,
+    Actor, ApiKeyCreate, AuditAction, AuditQuery, GrantContext, GrantSkill, GrantTitle,
+    ProfileCreate, ProfileLogin, RevokeSkill, SecondaryToken, SecondaryTokenCreate, SkillManager,
+    SkillName, StrawError,
 };
-use axum::http::HeaderMap;
-use dorsal::DefaultReturn;
+use crate::{jwt, oauth};
+use axum::http::{header, HeaderMap, HeaderName, StatusCode};
+use dorsal::{utility, DefaultReturn};
 
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::{
     extract::{Path, Query, State},
     routing::{get, post},
     Json, Router,
 };
-use axum_extra::extract::cookie::CookieJar;
 
 pub fn routes(database: Database) -> Router {
     Router::new()
@@ -22,24 +24,75 @@ pub fn routes(database: Database) -> Router {
         .route("/spirit/:username/revoke", post(revoke_skill_request))
         .route("/spirit/:username/seed", post(grant_title_request))
         .route("/spirit/:username", get(spirit_inspect_request))
+        .route("/skills", get(skills_request))
+        // audit
+        .route("/audit", get(audit_request))
+        .route("/audit/export", get(audit_export_request))
         // me
         .route("/me", get(my_stats_request))
+        .route("/me/rotate", post(rotate_key_request))
+        .route("/me/handoff", post(create_handoff_request))
+        .route("/me/jwt", post(jwt::create_jwt_request))
+        .route("/me/sessions", get(my_sessions_request))
+        .route("/me/sessions/revoke", post(revoke_all_sessions_request))
+        .route("/me/sessions/:id/revoke", post(revoke_session_request))
+        .route(
+            "/me/tokens",
+            get(my_tokens_request).post(create_token_request),
+        )
+        .route("/me/tokens/:name/revoke", post(revoke_token_request))
+        .route(
+            "/me/keys",
+            get(my_api_keys_request).post(create_api_key_request),
+        )
+        .route("/me/keys/:id/revoke", post(revoke_api_key_request))
         // account
         .route("/start", post(create_profile_request))
         .route("/return", post(login_request))
-        .route("/travel", post(callback_request))
+        .route("/travel", get(callback_request))
         .route("/done", get(logout_request))
+        // oauth
+        .route("/oauth/clients", post(oauth::create_client_request))
+        .route(
+            "/oauth/authorize",
+            get(oauth::authorize_request).post(oauth::consent_request),
+        )
+        .route("/oauth/token", post(oauth::token_request))
+        .route("/oauth/introspect", post(oauth::introspect_request))
+        .route(
+            "/oauth/userinfo",
+            get(oauth::userinfo_request).post(oauth::userinfo_request),
+        )
+        .route(
+            "/.well-known/openid-configuration",
+            get(oauth::discovery_request),
+        )
+        .route("/.well-known/jwks.json", get(jwt::jwks_request))
         // ...
         .with_state(database)
 }
 
 /// [`Database::create_profile`]
 pub async fn create_profile_request(
-    jar: CookieJar,
+    headers: HeaderMap,
+    client: ClientInfo,
     State(database): State<Database>,
     Json(props): Json<ProfileCreate>,
 ) -> impl IntoResponse {
-    if let Some(_) = jar.get("__Secure-Token") {
+    if request_token(&headers, &database.config).is_some() {
+        return (
+            HeaderMap::new(),
+            serde_json::to_string(&DefaultReturn {
+                success: false,
+                message: StrawError::NotAllowed.to_string(),
+                payload: (),
+            })
+            .unwrap(),
+        );
+    }
+
+    // check registration policy
+    if !database.config.registration_allowed() {
         return (
             HeaderMap::new(),
             serde_json::to_string(&DefaultReturn {
@@ -51,7 +104,11 @@ pub async fn create_profile_request(
         );
     }
 
-    let res = match database.create_profile(props.username).await {
+    let username = props.username.to_lowercase();
+    let res = match database
+        .create_profile(props.username, &Actor::new(&username).ip(&client.ip))
+        .await
+    {
         Ok(r) => r,
         Err(e) => {
             return (
@@ -66,18 +123,30 @@ pub async fn create_profile_request(
         }
     };
 
+    // create session
+    let token = match database
+        .create_session(utility::hash(res.clone()), client.user_agent, client.ip)
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => {
+            return (
+                HeaderMap::new(),
+                serde_json::to_string(&DefaultReturn {
+                    success: false,
+                    message: e.to_string(),
+                    payload: (),
+                })
+                .unwrap(),
+            );
+        }
+    };
+
     // return
     let mut headers = HeaderMap::new();
-
     headers.insert(
         "Set-Cookie",
-        format!(
-            "__Secure-Token={}; SameSite=Lax; Secure; Path=/; HostOnly=true; HttpOnly=true; Max-Age={}",
-            res,
-            60* 60 * 24 * 365
-        )
-        .parse()
-        .unwrap(),
+        database.config.cookie.set(&token).parse().unwrap(),
     );
 
     (
@@ -93,33 +162,49 @@ pub async fn create_profile_request(
 
 /// [`Database::get_profile_by_unhashed_st`]
 pub async fn login_request(
+    client: ClientInfo,
     State(database): State<Database>,
     Json(props): Json<ProfileLogin>,
 ) -> impl IntoResponse {
-    if let Err(e) = database.get_profile_by_unhashed(props.id.clone()).await {
-        return (
-            HeaderMap::new(),
-            serde_json::to_string(&DefaultReturn {
-                success: false,
-                message: e.to_string(),
-                payload: (),
-            })
-            .unwrap(),
-        );
+    let profile = match database.get_profile_by_unhashed(props.id.clone()).await {
+        Ok(p) => p,
+        Err(e) => {
+            return (
+                HeaderMap::new(),
+                serde_json::to_string(&DefaultReturn {
+                    success: false,
+                    message: e.to_string(),
+                    payload: (),
+                })
+                .unwrap(),
+            );
+        }
+    };
+
+    // create session
+    let token = match database
+        .create_session(profile.id, client.user_agent, client.ip)
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => {
+            return (
+                HeaderMap::new(),
+                serde_json::to_string(&DefaultReturn {
+                    success: false,
+                    message: e.to_string(),
+                    payload: (),
+                })
+                .unwrap(),
+            );
+        }
     };
 
     // return
     let mut headers = HeaderMap::new();
-
     headers.insert(
         "Set-Cookie",
-        format!(
-            "__Secure-Token={}; SameSite=Lax; Secure; Path=/; HostOnly=true; HttpOnly=true; Max-Age={}",
-            props.id,
-            60* 60 * 24 * 365
-        )
-        .parse()
-        .unwrap(),
+        database.config.cookie.set(&token).parse().unwrap(),
     );
 
     (
@@ -134,307 +219,498 @@ pub async fn login_request(
 }
 
 /// [`SkillManager::get_stats`]
-pub async fn my_stats_request(
-    jar: CookieJar,
-    State(database): State<Database>,
-) -> impl IntoResponse {
-    // get user from token
-    let auth_user = match jar.get("__Secure-Token") {
-        Some(c) => match database
-            .get_profile_by_unhashed(c.value_trimmed().to_string())
-            .await
-        {
-            Ok(ua) => ua,
-            Err(e) => {
-                return Json(DefaultReturn {
-                    success: false,
-                    message: e.to_string(),
-                    payload: None,
-                });
-            }
-        },
-        None => {
-            return Json(DefaultReturn {
-                success: false,
-                message: StrawError::NotAllowed.to_string(),
-                payload: None,
-            });
-        }
-    };
-
-    // create manager
-    let manager = SkillManager(auth_user.skills);
-
-    // return
+pub async fn my_stats_request(auth: AuthProfile) -> impl IntoResponse {
     Json(DefaultReturn {
         success: true,
-        message: auth_user.username.to_string(),
-        payload: Some(manager.get_stats()),
+        message: auth.profile.username,
+        payload: Some(auth.stats),
     })
 }
 
-/// [`SkillManager::get_stats`]
-pub async fn spirit_inspect_request(
-    Path(username): Path<String>,
+/// [`Database::get_sessions_by_profile`]
+///
+/// The message is the ID of the session used to make the request (if any).
+pub async fn my_sessions_request(
+    auth: AuthProfile,
     State(database): State<Database>,
 ) -> impl IntoResponse {
-    // get user
-    let auth_user = match database.get_profile_by_username(username).await {
-        Ok(ua) => ua,
-        Err(e) => {
-            return Json(DefaultReturn {
-                success: false,
-                message: e.to_string(),
-                payload: None,
-            });
-        }
+    let current = match auth.session {
+        Some(s) => s.id,
+        None => String::new(),
     };
 
-    // create manager
-    let manager = SkillManager(auth_user.skills);
-
-    // check vanish skill
-    if manager
-        .get_stats()
-        .abilities
-        .get(&SkillName::Vanish)
-        .is_some()
-    {
-        return Json(DefaultReturn {
+    match database.get_sessions_by_profile(auth.profile.id).await {
+        Ok(sessions) => Json(DefaultReturn {
+            success: true,
+            message: current,
+            payload: Some(sessions),
+        }),
+        Err(e) => Json(DefaultReturn {
             success: false,
-            message: StrawError::NotAllowed.to_string(),
+            message: e.to_string(),
             payload: None,
-        });
+        }),
     }
-
-    // return
-    Json(DefaultReturn {
-        success: true,
-        message: auth_user.username.to_string(),
-        payload: Some(manager.get_stats()),
-    })
 }
 
-/// [`SkillManager::push`]
-pub async fn grant_skill_request(
-    jar: CookieJar,
-    Path(username): Path<String>,
+/// [`Database::delete_session`]
+pub async fn revoke_session_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    Path(id): Path<String>,
     State(database): State<Database>,
-    Json(props): Json<GrantSkill>,
 ) -> impl IntoResponse {
-    // get user from token
-    let auth_user = match jar.get("__Secure-Token") {
-        Some(c) => match database
-            .get_profile_by_unhashed(c.value_trimmed().to_string())
-            .await
-        {
-            Ok(ua) => ua,
-            Err(e) => {
-                return Json(DefaultReturn {
-                    success: false,
-                    message: e.to_string(),
-                    payload: None,
-                });
-            }
-        },
-        None => {
-            return Json(DefaultReturn {
-                success: false,
-                message: StrawError::NotAllowed.to_string(),
-                payload: None,
-            });
-        }
-    };
-
-    // check permission
-    let manager = SkillManager(auth_user.skills);
-    let stats = manager.get_stats();
-
-    if stats.title != SkillName::God {
-        // we must have the "God" title to edit other users
+    // api keys can't act with the full profile
+    if auth.api_key.is_some() {
         return Json(DefaultReturn {
             success: false,
             message: StrawError::NotAllowed.to_string(),
-            payload: None,
+            payload: (),
         });
     }
 
-    // get other user
-    let other_user = match database.get_profile_by_username(username.clone()).await {
-        Ok(ua) => ua,
-        Err(e) => {
-            return Json(DefaultReturn {
-                success: false,
-                message: e.to_string(),
-                payload: None,
-            });
-        }
-    };
-
-    let mut manager = SkillManager(other_user.skills);
+    match database
+        .delete_session(
+            auth.profile.id,
+            id,
+            &Actor::new(&auth.profile.username).ip(&client.ip),
+        )
+        .await
+    {
+        Ok(_) => Json(DefaultReturn {
+            success: true,
+            message: "Acceptable".to_string(),
+            payload: (),
+        }),
+        Err(e) => Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: (),
+        }),
+    }
+}
 
-    // grant skill
-    if let Err(e) = manager.push(props.skill) {
+/// [`Database::delete_sessions_by_profile`]
+pub async fn revoke_all_sessions_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    State(database): State<Database>,
+) -> impl IntoResponse {
+    // api keys can't act with the full profile
+    if auth.api_key.is_some() {
         return Json(DefaultReturn {
             success: false,
-            message: e.to_string(),
-            payload: None,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
         });
     }
 
-    // push update
-    // TODO: try not to clone
-    if let Err(e) = database
-        .edit_profile_skills_by_name(username, manager.0.clone())
+    match database
+        .delete_sessions_by_profile(
+            auth.profile.id,
+            &Actor::new(&auth.profile.username).ip(&client.ip),
+        )
         .await
     {
-        return Json(DefaultReturn {
+        Ok(_) => Json(DefaultReturn {
+            success: true,
+            message: "Acceptable".to_string(),
+            payload: (),
+        }),
+        Err(e) => Json(DefaultReturn {
             success: false,
             message: e.to_string(),
-            payload: None,
-        });
+            payload: (),
+        }),
     }
-
-    // return
-    Json(DefaultReturn {
-        success: true,
-        message: "Acceptable".to_string(),
-        payload: Some(manager.0),
-    })
 }
 
-/// [`SkillManager::remove`]
-pub async fn revoke_skill_request(
-    jar: CookieJar,
-    Path(username): Path<String>,
+/// [`Database::rotate_profile_key`]
+///
+/// The new unhashed ID is returned as the message, and a new session is created
+/// for the current client since every other session is removed.
+pub async fn rotate_key_request(
+    auth: AuthProfile,
+    client: ClientInfo,
     State(database): State<Database>,
-    Json(props): Json<RevokeSkill>,
 ) -> impl IntoResponse {
-    // get user from token
-    let auth_user = match jar.get("__Secure-Token") {
-        Some(c) => match database
-            .get_profile_by_unhashed(c.value_trimmed().to_string())
-            .await
-        {
-            Ok(ua) => ua,
-            Err(e) => {
-                return Json(DefaultReturn {
-                    success: false,
-                    message: e.to_string(),
-                    payload: None,
-                });
-            }
-        },
-        None => {
-            return Json(DefaultReturn {
+    // api keys can't act with the full profile
+    if auth.api_key.is_some() {
+        return (
+            HeaderMap::new(),
+            Json(DefaultReturn {
                 success: false,
                 message: StrawError::NotAllowed.to_string(),
-                payload: None,
-            });
+                payload: (),
+            }),
+        );
+    }
+
+    let res = match database
+        .rotate_profile_key(
+            auth.profile.username.clone(),
+            &Actor::new(&auth.profile.username).ip(&client.ip),
+        )
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            return (
+                HeaderMap::new(),
+                Json(DefaultReturn {
+                    success: false,
+                    message: e.to_string(),
+                    payload: (),
+                }),
+            );
         }
     };
 
-    // check permission
-    let manager = SkillManager(auth_user.skills);
-    let stats = manager.get_stats();
+    // create session
+    let token = match database
+        .create_session(utility::hash(res.clone()), client.user_agent, client.ip)
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => {
+            return (
+                HeaderMap::new(),
+                Json(DefaultReturn {
+                    success: false,
+                    message: e.to_string(),
+                    payload: (),
+                }),
+            );
+        }
+    };
 
-    if stats.title != SkillName::God {
-        // we must have the "God" title to edit other users
-        return Json(DefaultReturn {
-            success: false,
-            message: StrawError::NotAllowed.to_string(),
-            payload: None,
-        });
-    }
+    // return
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Set-Cookie",
+        database.config.cookie.set(&token).parse().unwrap(),
+    );
 
-    // get other user
-    let other_user = match database.get_profile_by_username(username.clone()).await {
-        Ok(ua) => ua,
+    (
+        headers,
+        Json(DefaultReturn {
+            success: true,
+            message: res,
+            payload: (),
+        }),
+    )
+}
+
+/// List the [`SecondaryToken`]s of the current profile (without their hashes)
+pub async fn my_tokens_request(
+    auth: AuthProfile,
+    State(database): State<Database>,
+) -> impl IntoResponse {
+    let tokens: Vec<SecondaryToken> = match database.get_secondary_tokens(auth.profile.id).await {
+        Ok(t) => t
+            .into_iter()
+            .map(|t| SecondaryToken {
+                token: String::new(),
+                ..t
+            })
+            .collect(),
         Err(e) => {
             return Json(DefaultReturn {
                 success: false,
                 message: e.to_string(),
-                payload: None,
-            });
+                payload: Vec::new(),
+            })
         }
     };
 
-    let mut manager = SkillManager(other_user.skills);
+    Json(DefaultReturn {
+        success: true,
+        message: auth.profile.username,
+        payload: tokens,
+    })
+}
 
-    // revoke skill
-    if let Err(e) = manager.remove(props.skill) {
+/// [`Database::create_secondary_token`]
+///
+/// The unhashed token is returned as the message, and cannot be viewed again.
+pub async fn create_token_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    State(database): State<Database>,
+    Json(props): Json<SecondaryTokenCreate>,
+) -> impl IntoResponse {
+    // api keys can't act with the full profile
+    if auth.api_key.is_some() {
         return Json(DefaultReturn {
             success: false,
-            message: e.to_string(),
-            payload: None,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
         });
     }
 
-    // push update
-    // TODO: try not to clone
-    if let Err(e) = database
-        .edit_profile_skills_by_name(username, manager.0.clone())
+    match database
+        .create_secondary_token(
+            auth.profile.username.clone(),
+            props.name,
+            &Actor::new(&auth.profile.username).ip(&client.ip),
+        )
         .await
     {
-        return Json(DefaultReturn {
+        Ok(token) => Json(DefaultReturn {
+            success: true,
+            message: token,
+            payload: (),
+        }),
+        Err(e) => Json(DefaultReturn {
             success: false,
             message: e.to_string(),
+            payload: (),
+        }),
+    }
+}
+
+/// [`Database::delete_secondary_token`]
+pub async fn revoke_token_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    Path(name): Path<String>,
+    State(database): State<Database>,
+) -> impl IntoResponse {
+    // api keys can't act with the full profile
+    if auth.api_key.is_some() {
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
+        });
+    }
+
+    match database
+        .delete_secondary_token(
+            auth.profile.username.clone(),
+            name,
+            &Actor::new(&auth.profile.username).ip(&client.ip),
+        )
+        .await
+    {
+        Ok(_) => Json(DefaultReturn {
+            success: true,
+            message: "Acceptable".to_string(),
+            payload: (),
+        }),
+        Err(e) => Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: (),
+        }),
+    }
+}
+
+/// [`Database::get_api_keys_by_profile`]
+pub async fn my_api_keys_request(
+    auth: AuthProfile,
+    State(database): State<Database>,
+) -> impl IntoResponse {
+    match database.get_api_keys_by_profile(auth.profile.id).await {
+        Ok(keys) => Json(DefaultReturn {
+            success: true,
+            message: auth.profile.username,
+            payload: Some(keys),
+        }),
+        Err(e) => Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: None,
+        }),
+    }
+}
+
+/// [`Database::create_api_key`]
+///
+/// The unhashed key is returned as the message, and cannot be viewed again.
+pub async fn create_api_key_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    State(database): State<Database>,
+    Json(props): Json<ApiKeyCreate>,
+) -> impl IntoResponse {
+    // api keys can't create other api keys
+    if auth.api_key.is_some() {
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
             payload: None,
         });
     }
 
-    // return
+    match database
+        .create_api_key(
+            auth.profile.id,
+            props.label,
+            props.scope,
+            props.expires_in,
+            &Actor::new(&auth.profile.username).ip(&client.ip),
+        )
+        .await
+    {
+        Ok((key, token)) => Json(DefaultReturn {
+            success: true,
+            message: token,
+            payload: Some(key),
+        }),
+        Err(e) => Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: None,
+        }),
+    }
+}
+
+/// [`Database::delete_api_key`]
+pub async fn revoke_api_key_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    Path(id): Path<String>,
+    State(database): State<Database>,
+) -> impl IntoResponse {
+    // api keys can't act with the full profile
+    if auth.api_key.is_some() {
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
+        });
+    }
+
+    match database
+        .delete_api_key(
+            auth.profile.id,
+            id,
+            &Actor::new(&auth.profile.username).ip(&client.ip),
+        )
+        .await
+    {
+        Ok(_) => Json(DefaultReturn {
+            success: true,
+            message: "Acceptable".to_string(),
+            payload: (),
+        }),
+        Err(e) => Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: (),
+        }),
+    }
+}
+
+/// The `ETag` header for a [`Profile`](crate::model::Profile) at the given version
+fn etag(version: u64) -> [(HeaderName, String); 1] {
+    [(header::ETAG, format!("\"{version}\""))]
+}
+
+/// Get the version an edit expects the profile to be at
+///
+/// Uses the `If-Match` header when given, which is either `*` or a list of entity tags
+/// (compared strongly, so weak tags never match). Fails with [`StrawError::Conflict`]
+/// if none of them match `current`. Otherwise the version which was just read is used.
+fn expected_version(headers: &HeaderMap, current: u64) -> Result<u64, StrawError> {
+    let values: Vec<&str> = headers
+        .get_all(header::IF_MATCH)
+        .iter()
+        .map(|v| v.to_str().unwrap_or_default())
+        .collect();
+
+    if values.is_empty() {
+        return Ok(current);
+    }
+
+    // "*" matches any version, since the profile exists
+    let tag = format!("\"{current}\"");
+    let matches = values
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|t| t == "*" || t == tag);
+
+    if matches {
+        Ok(current)
+    } else {
+        Err(StrawError::Conflict)
+    }
+}
+
+/// [`SkillRegistry::skills`](crate::registry::SkillRegistry::skills)
+pub async fn skills_request(State(database): State<Database>) -> impl IntoResponse {
     Json(DefaultReturn {
         success: true,
         message: "Acceptable".to_string(),
-        payload: Some(manager.0),
+        payload: database.config.skills.skills().to_vec(),
     })
 }
 
-/// [`SkillManager::title`]
-pub async fn grant_title_request(
-    jar: CookieJar,
+/// [`SkillManager::get_stats`], with the [`SkillGrant`](crate::model::SkillGrant)s of
+/// each skill if the viewer has the "God" title
+pub async fn spirit_inspect_request(
+    OptionalAuthProfile(auth): OptionalAuthProfile,
     Path(username): Path<String>,
     State(database): State<Database>,
-    Json(props): Json<GrantTitle>,
-) -> impl IntoResponse {
-    // get user from token
-    let auth_user = match jar.get("__Secure-Token") {
-        Some(c) => match database
-            .get_profile_by_unhashed(c.value_trimmed().to_string())
-            .await
-        {
-            Ok(ua) => ua,
-            Err(e) => {
-                return Json(DefaultReturn {
-                    success: false,
-                    message: e.to_string(),
-                    payload: None,
-                });
-            }
-        },
-        None => {
+) -> Response {
+    // get user
+    let auth_user = match database.get_profile_by_username(username).await {
+        Ok(ua) => ua,
+        Err(e) => {
             return Json(DefaultReturn {
                 success: false,
-                message: StrawError::NotAllowed.to_string(),
-                payload: None,
-            });
+                message: e.to_string(),
+                payload: (),
+            })
+            .into_response();
         }
     };
 
-    // check permission
-    let manager = SkillManager(auth_user.skills);
-    let stats = manager.get_stats();
+    // create manager
+    let manager = SkillManager::from(&auth_user);
 
-    if stats.title != SkillName::God {
-        // we must have the "God" title to edit other users
+    // check vanish skill
+    if manager
+        .get_stats()
+        .abilities
+        .get(&SkillName::Vanish)
+        .is_some()
+    {
         return Json(DefaultReturn {
             success: false,
             message: StrawError::NotAllowed.to_string(),
-            payload: None,
-        });
+            payload: (),
+        })
+        .into_response();
     }
 
+    // return
+    (
+        etag(auth_user.version),
+        Json(DefaultReturn {
+            success: true,
+            message: auth_user.username.to_string(),
+            payload: Some(if auth.is_some_and(|a| a.stats.title == SkillName::God) {
+                manager.get_stats_with_grants()
+            } else {
+                manager.get_stats()
+            }),
+        }),
+    )
+        .into_response()
+}
+
+/// [`SkillManager::push`]
+pub async fn grant_skill_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    State(database): State<Database>,
+    Json(props): Json<GrantSkill>,
+) -> Response {
     // get other user
     let other_user = match database.get_profile_by_username(username.clone()).await {
         Ok(ua) => ua,
@@ -442,41 +718,391 @@ pub async fn grant_title_request(
             return Json(DefaultReturn {
                 success: false,
                 message: e.to_string(),
-                payload: None,
-            });
+                payload: (),
+            })
+            .into_response();
+        }
+    };
+
+    let version = match expected_version(&headers, other_user.version) {
+        Ok(v) => v,
+        Err(e) => {
+            return (
+                StatusCode::PRECONDITION_FAILED,
+                Json(DefaultReturn::<u16> {
+                    success: false,
+                    message: e.to_string(),
+                    payload: 412,
+                }),
+            )
+                .into_response()
         }
     };
 
-    let mut manager = SkillManager(other_user.skills);
+    let mut manager = SkillManager::from(&other_user);
 
-    // set title
-    if let Err(e) = manager.title(props.title.into()) {
+    // grant skill
+    let context = GrantContext::by(&auth.profile.id)
+        .reason(props.reason.clone())
+        .expires_at(props.expires_at);
+
+    if let Err(e) = manager.push(&database.config.skills, props.skill, &context) {
         return Json(DefaultReturn {
             success: false,
             message: e.to_string(),
-            payload: None,
-        });
+            payload: (),
+        })
+        .into_response();
+    }
+
+    // check permission
+    if !auth
+        .manager()
+        .delegate(SkillManager::from(&other_user), &manager)
+    {
+        // we must be able to act on the other user, and can't make them stronger than us
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
+        })
+        .into_response();
     }
 
     // push update
     // TODO: try not to clone
-    if let Err(e) = database
-        .edit_profile_skills_by_name(username, manager.0.clone())
+    let version = match database
+        .edit_profile_skills_by_name(
+            username.clone(),
+            manager.skills().clone(),
+            manager.grants().to_vec(),
+            version,
+            &Actor::new(&auth.profile.username)
+                .ip(&client.ip)
+                .reason(props.reason),
+            AuditAction::Grant,
+        )
         .await
     {
+        Ok(v) => v,
+        Err(StrawError::Conflict) => return StrawError::Conflict.into_response(),
+        Err(e) => {
+            return Json(DefaultReturn {
+                success: false,
+                message: e.to_string(),
+                payload: (),
+            })
+            .into_response();
+        }
+    };
+
+    // return
+    (
+        etag(version),
+        Json(DefaultReturn {
+            success: true,
+            message: "Acceptable".to_string(),
+            payload: Some(manager.into_parts().0),
+        }),
+    )
+        .into_response()
+}
+
+/// [`SkillManager::remove`]
+pub async fn revoke_skill_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    State(database): State<Database>,
+    Json(props): Json<RevokeSkill>,
+) -> Response {
+    // get other user
+    let other_user = match database.get_profile_by_username(username.clone()).await {
+        Ok(ua) => ua,
+        Err(e) => {
+            return Json(DefaultReturn {
+                success: false,
+                message: e.to_string(),
+                payload: (),
+            })
+            .into_response();
+        }
+    };
+
+    let version = match expected_version(&headers, other_user.version) {
+        Ok(v) => v,
+        Err(e) => {
+            return (
+                StatusCode::PRECONDITION_FAILED,
+                Json(DefaultReturn::<u16> {
+                    success: false,
+                    message: e.to_string(),
+                    payload: 412,
+                }),
+            )
+                .into_response()
+        }
+    };
+
+    let mut manager = SkillManager::from(&other_user);
+
+    // revoke skill
+    if database.config.skills.get(&props.skill).is_none() {
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::ValueError.to_string(),
+            payload: (),
+        })
+        .into_response();
+    }
+
+    if let Err(e) = manager.remove(
+        props.skill,
+        &GrantContext::by(&auth.profile.id).reason(props.reason.clone()),
+    ) {
         return Json(DefaultReturn {
             success: false,
             message: e.to_string(),
-            payload: None,
-        });
+            payload: (),
+        })
+        .into_response();
     }
 
+    // check permission
+    if !auth
+        .manager()
+        .delegate(SkillManager::from(&other_user), &manager)
+    {
+        // we must be able to act on the other user, and can't make them stronger than us
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
+        })
+        .into_response();
+    }
+
+    // push update
+    // TODO: try not to clone
+    let version = match database
+        .edit_profile_skills_by_name(
+            username.clone(),
+            manager.skills().clone(),
+            manager.grants().to_vec(),
+            version,
+            &Actor::new(&auth.profile.username)
+                .ip(&client.ip)
+                .reason(props.reason),
+            AuditAction::Revoke,
+        )
+        .await
+    {
+        Ok(v) => v,
+        Err(StrawError::Conflict) => return StrawError::Conflict.into_response(),
+        Err(e) => {
+            return Json(DefaultReturn {
+                success: false,
+                message: e.to_string(),
+                payload: (),
+            })
+            .into_response();
+        }
+    };
+
     // return
-    Json(DefaultReturn {
-        success: true,
-        message: "Acceptable".to_string(),
-        payload: Some(manager.0),
-    })
+    (
+        etag(version),
+        Json(DefaultReturn {
+            success: true,
+            message: "Acceptable".to_string(),
+            payload: Some(manager.into_parts().0),
+        }),
+    )
+        .into_response()
+}
+
+/// [`SkillManager::title`]
+pub async fn grant_title_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    State(database): State<Database>,
+    Json(props): Json<GrantTitle>,
+) -> Response {
+    // get other user
+    let other_user = match database.get_profile_by_username(username.clone()).await {
+        Ok(ua) => ua,
+        Err(e) => {
+            return Json(DefaultReturn {
+                success: false,
+                message: e.to_string(),
+                payload: (),
+            })
+            .into_response();
+        }
+    };
+
+    let version = match expected_version(&headers, other_user.version) {
+        Ok(v) => v,
+        Err(e) => {
+            return (
+                StatusCode::PRECONDITION_FAILED,
+                Json(DefaultReturn::<u16> {
+                    success: false,
+                    message: e.to_string(),
+                    payload: 412,
+                }),
+            )
+                .into_response()
+        }
+    };
+
+    let mut manager = SkillManager::from(&other_user);
+
+    // set title
+    let title = match database.config.skills.skill(&props.title) {
+        Ok(t) => t,
+        Err(e) => {
+            return Json(DefaultReturn {
+                success: false,
+                message: e.to_string(),
+                payload: (),
+            })
+            .into_response();
+        }
+    };
+
+    let context = GrantContext::by(&auth.profile.id)
+        .reason(props.reason.clone())
+        .expires_at(props.expires_at);
+
+    if let Err(e) = manager.title(&database.config.skills, title, &context) {
+        return Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: (),
+        })
+        .into_response();
+    }
+
+    // check permission
+    if !auth
+        .manager()
+        .delegate(SkillManager::from(&other_user), &manager)
+    {
+        // we must be able to act on the other user, and can't make them stronger than us
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
+        })
+        .into_response();
+    }
+
+    // push update
+    // TODO: try not to clone
+    let version = match database
+        .edit_profile_skills_by_name(
+            username.clone(),
+            manager.skills().clone(),
+            manager.grants().to_vec(),
+            version,
+            &Actor::new(&auth.profile.username)
+                .ip(&client.ip)
+                .reason(props.reason),
+            AuditAction::Title,
+        )
+        .await
+    {
+        Ok(v) => v,
+        Err(StrawError::Conflict) => return StrawError::Conflict.into_response(),
+        Err(e) => {
+            return Json(DefaultReturn {
+                success: false,
+                message: e.to_string(),
+                payload: (),
+            })
+            .into_response();
+        }
+    };
+
+    // return
+    (
+        etag(version),
+        Json(DefaultReturn {
+            success: true,
+            message: "Acceptable".to_string(),
+            payload: Some(manager.into_parts().0),
+        }),
+    )
+        .into_response()
+}
+
+// audit
+/// [`Database::get_audit_entries`]
+pub async fn audit_request(
+    auth: AuthProfile,
+    State(database): State<Database>,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    // check permission
+    if auth.stats.title != SkillName::God {
+        return StrawError::NotAllowed.into_response();
+    }
+
+    // return
+    match database.get_audit_entries(&query).await {
+        Ok(entries) => Json(DefaultReturn {
+            success: true,
+            message: "Acceptable".to_string(),
+            payload: Some(entries),
+        })
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Every matching [`AuditEntry`](crate::model::AuditEntry) as JSON Lines, newest first
+///
+/// `limit` and `offset` are ignored. Pages are read by timestamp and ID, so entries
+/// added during the export are left out instead of repeating older ones.
+pub async fn audit_export_request(
+    auth: AuthProfile,
+    State(database): State<Database>,
+    Query(mut query): Query<AuditQuery>,
+) -> Response {
+    // check permission
+    if auth.stats.title != SkillName::God {
+        return StrawError::NotAllowed.into_response();
+    }
+
+    // read every page
+    let mut body = String::new();
+    query.limit = 500;
+    query.offset = 0;
+
+    loop {
+        let entries = match database.get_audit_entries(&query).await {
+            Ok(e) => e,
+            Err(e) => return e.into_response(),
+        };
+
+        for entry in &entries {
+            body.push_str(&serde_json::to_string(entry).unwrap());
+            body.push('\n');
+        }
+
+        if entries.len() < query.limit {
+            break;
+        }
+
+        query.before = entries.last().map(|e| (e.timestamp, e.id.clone()));
+    }
+
+    // return
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response()
 }
 
 // general
@@ -489,41 +1115,121 @@ pub async fn not_found() -> impl IntoResponse {
 }
 
 // auth
+/// [`Database::create_handoff_code`]
+///
+/// The code is returned as the message and can be exchanged once at `/travel`.
+pub async fn create_handoff_request(
+    auth: AuthProfile,
+    State(database): State<Database>,
+) -> impl IntoResponse {
+    // api keys can't act with the full profile
+    if auth.api_key.is_some() {
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
+        });
+    }
+
+    match database.create_handoff_code(auth.profile.id).await {
+        Ok(code) => Json(DefaultReturn {
+            success: true,
+            message: code,
+            payload: (),
+        }),
+        Err(e) => Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: (),
+        }),
+    }
+}
+
 #[derive(serde::Deserialize)]
 pub struct CallbackQueryProps {
-    pub uid: String, // this uid will need to be sent to the client as a token
+    /// A handoff code created by [`create_handoff_request`]
+    pub code: String,
+    /// The path to redirect to, must be allowed by [`crate::ServerOptions::redirect_allowlist`]
+    #[serde(default = "default_redirect")]
+    pub to: String,
+}
+
+fn default_redirect() -> String {
+    "/".to_string()
 }
 
-pub async fn callback_request(Query(params): Query<CallbackQueryProps>) -> impl IntoResponse {
+/// [`Database::consume_handoff_code`]
+pub async fn callback_request(
+    client: ClientInfo,
+    State(database): State<Database>,
+    Query(params): Query<CallbackQueryProps>,
+) -> Response {
+    // check redirect
+    if !database.config.redirect_allowed(&params.to) {
+        return StrawError::ValueError.into_response();
+    }
+
+    // exchange code
+    let profile = match database.consume_handoff_code(params.code).await {
+        Ok(p) => p,
+        Err(_) => return StrawError::NotAllowed.into_response(),
+    };
+
+    // create session
+    let token = match database
+        .create_session(profile, client.user_agent, client.ip)
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => return e.into_response(),
+    };
+
     // return
+    let to = params
+        .to
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
     (
         [
             ("Content-Type".to_string(), "text/html".to_string()),
-            (
-                "Set-Cookie".to_string(),
-                format!(
-                    "__Secure-Token={}; SameSite=Lax; Secure; Path=/; HostOnly=true; HttpOnly=true; Max-Age={}",
-                    params.uid,
-                    60 * 60 * 24 * 365
-                ),
-            ),
+            ("Set-Cookie".to_string(), database.config.cookie.set(&token)),
         ],
-        "<head>
-            <meta http-equiv=\"Refresh\" content=\"0; URL=/\" />
+        format!(
+            "<head>
+            <meta http-equiv=\"Refresh\" content=\"0; URL={to}\" />
         </head>"
+        ),
     )
+        .into_response()
 }
 
-pub async fn logout_request(jar: CookieJar) -> impl IntoResponse {
-    // check for cookie
-    if let Some(_) = jar.get("__Secure-Token") {
+pub async fn logout_request(
+    headers: HeaderMap,
+    client: ClientInfo,
+    State(database): State<Database>,
+) -> impl IntoResponse {
+    // check for token
+    if let Some(token) = request_token(&headers, &database.config) {
+        // delete session
+        if let Ok(session) = database.get_session_by_unhashed(token).await {
+            if let Ok(p) = database
+                .get_profile_by_hashed(session.profile.clone())
+                .await
+            {
+                let actor = Actor::new(&p.username).ip(&client.ip);
+                let _ = database
+                    .delete_session(session.profile, session.id, &actor)
+                    .await;
+            }
+        }
+
         return (
             [
                 ("Content-Type".to_string(), "text/plain".to_string()),
-                (
-                    "Set-Cookie".to_string(),
-                   "__Secure-Token=refresh; SameSite=Strict; Secure; Path=/; HostOnly=true; HttpOnly=true; Max-Age=0".to_string(),
-                ),
+                ("Set-Cookie".to_string(), database.config.cookie.remove()),
             ],
             "You have been signed out. You can now close this tab.",
         );
@@ -0,0 +1,491 @@
+//! `starstraw-admin` - manage profiles directly through the database
+//!
+//! Connects using [`Database::env_options`], so the same `DB_*` variables (or `.env`
+//! file) as the server are used.
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use starstraw::model::{
+    Actor, AuditAction, GrantContext, Profile, Skill, SkillManager, SkillName, StrawError,
+};
+use starstraw::{Database, ServerOptions, SkillRegistry};
+
+#[derive(Parser)]
+#[command(name = "starstraw-admin", about = "Manage Starstraw profiles", version)]
+struct Cli {
+    /// Print JSON instead of tables
+    #[arg(long, global = true)]
+    json: bool,
+    /// A TOML or JSON file defining custom skills
+    #[arg(long, global = true)]
+    skills: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List every profile
+    List {
+        /// Only list profiles which hold the given skill or title
+        #[arg(long, value_parser = parse_skill)]
+        skill: Option<SkillName>,
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+    },
+    /// Search profiles by username
+    Search {
+        query: String,
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+    },
+    /// Show the computed stats of a profile
+    Stats { username: String },
+    /// Create a profile and print its account key
+    ///
+    /// The owner (`STRAW_OWNER`) is granted the "God" title if nobody has been before.
+    Create { username: String },
+    /// Grant the "God" title to the owner (`STRAW_OWNER`) if nobody has been before
+    Bootstrap,
+    /// Grant a skill to a profile
+    Grant {
+        username: String,
+        #[arg(value_parser = parse_skill)]
+        skill: SkillName,
+        /// Use a different value than the skill's default
+        #[arg(long)]
+        value: Option<f32>,
+        /// Remove the skill again after the given number of seconds
+        #[arg(long)]
+        expires_in: Option<u64>,
+        /// Why the skill is being granted
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Revoke a skill from a profile
+    Revoke {
+        username: String,
+        #[arg(value_parser = parse_skill)]
+        skill: SkillName,
+        /// Why the skill is being revoked
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Set the title of a profile
+    Title {
+        username: String,
+        #[arg(value_parser = parse_skill)]
+        title: SkillName,
+        /// Restore the current title after the given number of seconds
+        #[arg(long)]
+        expires_in: Option<u64>,
+        /// Why the title is being set
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Replace the account key of a profile, signing it out everywhere
+    Rotate { username: String },
+    /// Delete a profile and everything which belongs to it
+    Delete {
+        username: String,
+        /// Confirm the deletion
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Remove every expired skill now, instead of waiting for the server to
+    Expire,
+    /// Apply pending schema migrations
+    Migrate,
+}
+
+/// Create a [`GrantContext`] for a change made through the CLI
+///
+/// # Arguments:
+/// * `expires_in` - the number of seconds a granted skill is held for
+fn context(reason: Option<String>, expires_in: Option<u64>) -> GrantContext {
+    GrantContext::default()
+        .reason(reason)
+        .expires_at(expires_in.map(|s| dorsal::utility::unix_epoch_timestamp() + s as u128 * 1000))
+}
+
+/// Get the name of a skill as it's written in skill files (`"Manager"`)
+fn name(skill: &SkillName) -> String {
+    match serde_json::to_value(skill) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => format!("{skill:?}"),
+    }
+}
+
+/// Parse a [`SkillName`] from its name (`"Manager"`)
+fn parse_skill(value: &str) -> Result<SkillName, String> {
+    Ok(SkillName::from(value))
+}
+
+/// Get a registered skill with its default value, exiting if it isn't registered
+fn lookup(registry: &SkillRegistry, skill: &SkillName) -> Skill {
+    match registry.get(skill) {
+        Some(d) => d.skill(),
+        None => {
+            eprintln!("error: unknown skill \"{}\"", name(skill));
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Print the given rows as an aligned table
+fn table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    let line = |cells: Vec<String>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{c:<width$}", width = widths[i]))
+            .collect();
+
+        println!("{}", padded.join("  ").trim_end());
+    };
+
+    line(header.iter().map(|h| h.to_uppercase()).collect());
+
+    for row in rows {
+        line(row);
+    }
+}
+
+/// Print the given value as JSON, or with the given fallback
+fn output<T: Serialize>(json: bool, value: &T, human: impl FnOnce(&T)) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value).unwrap());
+    } else {
+        human(value)
+    }
+}
+
+fn profiles(json: bool, profiles: Vec<Profile>) {
+    output(json, &profiles, |profiles| {
+        table(
+            &["username", "title", "skills", "joined"],
+            profiles
+                .iter()
+                .map(|p| {
+                    let stats = SkillManager::from(p).get_stats();
+                    vec![
+                        p.username.clone(),
+                        name(&stats.title),
+                        p.skills.len().to_string(),
+                        p.joined.to_string(),
+                    ]
+                })
+                .collect(),
+        )
+    })
+}
+
+fn skills(json: bool, manager: SkillManager) {
+    output(json, manager.skills(), |skills| {
+        table(
+            &[
+                "type", "skill", "value", "granted", "by", "reason", "expires",
+            ],
+            skills
+                .iter()
+                .map(|s| {
+                    let grant = manager.grants().iter().find(|g| g.name == s.0 .1);
+
+                    vec![
+                        format!("{:?}", s.0 .0),
+                        name(&s.0 .1),
+                        s.1.to_string(),
+                        grant.map(|g| g.granted_at.to_string()).unwrap_or_default(),
+                        grant.map(|g| g.granted_by.clone()).unwrap_or_default(),
+                        grant.and_then(|g| g.reason.clone()).unwrap_or_default(),
+                        grant
+                            .and_then(|g| g.expires_at)
+                            .map(|e| e.to_string())
+                            .unwrap_or_default(),
+                    ]
+                })
+                .collect(),
+        )
+    })
+}
+
+async fn run(cli: Cli, database: Database) -> Result<(), StrawError> {
+    match cli.command {
+        Command::List {
+            skill,
+            limit,
+            offset,
+        } => {
+            let list = match skill {
+                Some(skill) => database
+                    .get_profiles_with_skill(skill)
+                    .await?
+                    .into_iter()
+                    .skip(offset)
+                    .take(limit)
+                    .collect(),
+                None => database.get_profiles(String::new(), limit, offset).await?,
+            };
+
+            profiles(cli.json, list);
+        }
+        Command::Search {
+            query,
+            limit,
+            offset,
+        } => {
+            profiles(cli.json, database.get_profiles(query, limit, offset).await?);
+        }
+        Command::Stats { username } => {
+            let profile = database.get_profile_by_username(username).await?;
+            let stats = SkillManager::from(&profile).get_stats();
+
+            output(cli.json, &stats, |stats| {
+                table(
+                    &["title", "power", "defense", "abilities"],
+                    vec![vec![
+                        name(&stats.title),
+                        stats.power.to_string(),
+                        stats.defense.to_string(),
+                        stats
+                            .abilities
+                            .keys()
+                            .map(name)
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                    ]],
+                );
+
+                println!();
+                skills(
+                    false,
+                    SkillManager::with_grants(stats.skills.clone(), profile.grants.clone()),
+                );
+            });
+        }
+        Command::Create { username } => {
+            let id = database
+                .create_profile(username.clone(), &Actor::system())
+                .await?;
+
+            // the owner is only ever bootstrapped by an operator, so nobody can take the
+            // title by signing up with their username first
+            if database.config.owner.as_ref().map(|o| o.to_lowercase())
+                == Some(username.to_lowercase())
+            {
+                database.bootstrap_owner().await?;
+            }
+
+            output(cli.json, &serde_json::json!({ "id": id }), |_| {
+                println!("Account key: {id}")
+            });
+        }
+        Command::Bootstrap => {
+            let owner = match database.config.owner {
+                Some(ref o) => o.to_lowercase(),
+                None => {
+                    eprintln!("error: no owner set (STRAW_OWNER)");
+                    std::process::exit(2);
+                }
+            };
+
+            let granted = database.bootstrap_owner().await?;
+            output(
+                cli.json,
+                &serde_json::json!({ "owner": owner, "granted": granted }),
+                |_| {
+                    if granted {
+                        println!("Granted \"{owner}\" the God title")
+                    } else {
+                        println!(
+                            "The owner has already been bootstrapped, or \"{owner}\" doesn't exist"
+                        )
+                    }
+                },
+            );
+        }
+        Command::Grant {
+            username,
+            skill,
+            value,
+            expires_in,
+            reason,
+        } => {
+            let registry = &database.config.skills;
+            let profile = database.get_profile_by_username(username.clone()).await?;
+            let mut manager = SkillManager::from(&profile);
+
+            let mut skill = lookup(registry, &skill);
+            if let Some(value) = value {
+                skill.1 = value;
+            }
+
+            manager.push(registry, skill, &context(reason.clone(), expires_in))?;
+            database
+                .edit_profile_skills_by_name(
+                    username,
+                    manager.skills().clone(),
+                    manager.grants().to_vec(),
+                    profile.version,
+                    &Actor::system().reason(reason),
+                    AuditAction::Grant,
+                )
+                .await?;
+
+            skills(cli.json, manager);
+        }
+        Command::Revoke {
+            username,
+            skill,
+            reason,
+        } => {
+            lookup(&database.config.skills, &skill);
+            let profile = database.get_profile_by_username(username.clone()).await?;
+            let mut manager = SkillManager::from(&profile);
+
+            if !manager.has_skill(skill.clone()) {
+                return Err(StrawError::NotFound);
+            }
+
+            manager.remove(skill, &context(reason.clone(), None))?;
+            database
+                .edit_profile_skills_by_name(
+                    username,
+                    manager.skills().clone(),
+                    manager.grants().to_vec(),
+                    profile.version,
+                    &Actor::system().reason(reason),
+                    AuditAction::Revoke,
+                )
+                .await?;
+
+            skills(cli.json, manager);
+        }
+        Command::Title {
+            username,
+            title,
+            expires_in,
+            reason,
+        } => {
+            let profile = database.get_profile_by_username(username.clone()).await?;
+            let mut manager = SkillManager::from(&profile);
+
+            let registry = &database.config.skills;
+            manager.title(
+                registry,
+                lookup(registry, &title),
+                &context(reason.clone(), expires_in),
+            )?;
+            database
+                .edit_profile_skills_by_name(
+                    username,
+                    manager.skills().clone(),
+                    manager.grants().to_vec(),
+                    profile.version,
+                    &Actor::system().reason(reason),
+                    AuditAction::Title,
+                )
+                .await?;
+
+            skills(cli.json, manager);
+        }
+        Command::Rotate { username } => {
+            let id = database
+                .rotate_profile_key(username.clone(), &Actor::system())
+                .await?;
+
+            output(cli.json, &serde_json::json!({ "id": id }), |_| {
+                println!("New account key: {id}")
+            });
+        }
+        Command::Delete { username, yes } => {
+            if !yes {
+                eprintln!("Refusing to delete \"{username}\" without --yes");
+                std::process::exit(2);
+            }
+
+            database
+                .delete_profile_by_name(username.clone(), &Actor::system())
+                .await?;
+
+            output(
+                cli.json,
+                &serde_json::json!({ "deleted": username }),
+                |_| println!("Deleted \"{username}\""),
+            );
+        }
+        Command::Expire => {
+            let updated = database.expire_skills().await?;
+
+            output(cli.json, &serde_json::json!({ "updated": updated }), |_| {
+                println!("Removed expired skills from {updated} profile(s)")
+            });
+        }
+        Command::Migrate => {
+            let applied = match database.migrate().await {
+                Ok(a) => a,
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+            };
+
+            output(cli.json, &applied, |applied| {
+                if applied.is_empty() {
+                    println!("Schema is up to date");
+                }
+
+                for id in applied {
+                    println!("Applied {id}");
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok(); // load .env
+    let cli = Cli::parse();
+
+    // load skills
+    let skills = match cli.skills {
+        Some(ref path) => match SkillRegistry::load(path) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+        },
+        None => SkillRegistry::default(),
+    };
+
+    // create database
+    let database = Database::new(
+        Database::env_options(),
+        ServerOptions {
+            owner: Database::env_owner(),
+            skills,
+            ..ServerOptions::truthy()
+        },
+    )
+    .await;
+
+    if let Err(e) = run(cli, database).await {
+        eprintln!("error: {}", e.to_string());
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,346 @@
+//! `starstraw` - serve the API routes from a TOML config file
+//!
+//! ```bash
+//! starstraw --config starstraw.toml
+//! ```
+//!
+//! See `starstraw.example.toml` for every option. Missing sections use the same
+//! defaults as [`ServerOptions::default`], and a missing `[database]` section falls
+//! back to [`Database::env_options`].
+use axum::http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{extract::State, Json, Router};
+use clap::Parser;
+use dorsal::DefaultReturn;
+use serde::Deserialize;
+use starstraw::model::Actor;
+use starstraw::{
+    CookieOptions, Database, DatabaseOpts, RegistrationPolicy, ServerOptions, SkillRegistry,
+};
+use std::net::SocketAddr;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+#[derive(Parser)]
+#[command(name = "starstraw", about = "Serve the Starstraw API", version)]
+struct Cli {
+    /// Path to the config file, `starstraw.toml` is used if it exists when not given
+    #[arg(long, short)]
+    config: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Config {
+    server: ServerConfig,
+    database: Option<DatabaseConfig>,
+    auth: AuthConfig,
+    cookie: CookieOptions,
+    cors: CorsConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ServerConfig {
+    /// The address to listen on
+    bind: String,
+    /// The path the API routes are nested under
+    prefix: String,
+    /// Read the client IP from `X-Forwarded-For` and `X-Real-IP`
+    trusted_proxy: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8080".to_string(),
+            prefix: "/".to_string(),
+            trusted_proxy: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DatabaseConfig {
+    #[serde(rename = "type")]
+    _type: Option<String>,
+    host: Option<String>,
+    #[serde(default)]
+    user: String,
+    #[serde(default)]
+    pass: String,
+    #[serde(default)]
+    name: String,
+}
+
+/// Overrides for [`ServerOptions`]
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct AuthConfig {
+    issuer: Option<String>,
+    owner: Option<String>,
+    redirect_allowlist: Option<Vec<String>>,
+    registration: Option<RegistrationPolicy>,
+    /// In seconds
+    jwt_lifetime: Option<u64>,
+    /// In seconds
+    key_rotation_interval: Option<u64>,
+    /// A TOML or JSON file defining custom skills
+    skills: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct CorsConfig {
+    /// Origins allowed to call the API, `"*"` allows any origin
+    origins: Vec<String>,
+    /// Allow cookies on cross-origin requests (not possible with `"*"`)
+    credentials: bool,
+}
+
+impl Config {
+    fn database_options(&self) -> DatabaseOpts {
+        match self.database {
+            Some(ref db) => DatabaseOpts {
+                _type: db._type.clone(),
+                host: db.host.clone(),
+                user: db.user.clone(),
+                pass: db.pass.clone(),
+                name: db.name.clone(),
+            },
+            None => Database::env_options(),
+        }
+    }
+
+    /// If the `"memory"` database type was chosen
+    fn in_memory(&self) -> bool {
+        self.database
+            .as_ref()
+            .and_then(|db| db._type.as_deref())
+            .is_some_and(|t| t == "memory")
+    }
+
+    fn server_options(&self) -> ServerOptions {
+        let defaults = ServerOptions::default();
+        let auth = &self.auth;
+
+        let skills = match auth.skills {
+            Some(ref path) => match SkillRegistry::load(path) {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+            },
+            None => defaults.skills,
+        };
+
+        ServerOptions {
+            issuer: auth.issuer.clone().unwrap_or(defaults.issuer),
+            owner: auth.owner.clone().or_else(Database::env_owner),
+            redirect_allowlist: auth
+                .redirect_allowlist
+                .clone()
+                .unwrap_or(defaults.redirect_allowlist),
+            registration: auth.registration.clone().unwrap_or(defaults.registration),
+            jwt_lifetime: auth
+                .jwt_lifetime
+                .map(|s| s as u128 * 1000)
+                .unwrap_or(defaults.jwt_lifetime),
+            key_rotation_interval: auth
+                .key_rotation_interval
+                .map(|s| s as u128 * 1000)
+                .unwrap_or(defaults.key_rotation_interval),
+            cookie: self.cookie.clone(),
+            skills,
+            trusted_proxy: self.server.trusted_proxy,
+            ..defaults
+        }
+    }
+
+    fn cors(&self) -> Option<CorsLayer> {
+        if self.cors.origins.is_empty() {
+            return None;
+        }
+
+        let layer = CorsLayer::new()
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_MATCH,
+                HeaderName::from_static("x-straw-token"),
+            ])
+            .expose_headers([header::ETAG]);
+
+        if self.cors.origins.iter().any(|o| o == "*") {
+            return Some(layer.allow_origin(AllowOrigin::any()));
+        }
+
+        let origins: Vec<HeaderValue> = self
+            .cors
+            .origins
+            .iter()
+            .filter_map(|o| o.parse().ok())
+            .collect();
+
+        Some(
+            layer
+                .allow_origin(origins)
+                .allow_credentials(self.cors.credentials),
+        )
+    }
+}
+
+/// Liveness check
+async fn health_request() -> impl IntoResponse {
+    Json(DefaultReturn {
+        success: true,
+        message: "Alive".to_string(),
+        payload: (),
+    })
+}
+
+/// Readiness check, makes sure the database can be reached
+async fn ready_request(State(database): State<Database>) -> impl IntoResponse {
+    let ready = database.ping().await.is_ok();
+
+    (
+        if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Json(DefaultReturn {
+            success: ready,
+            message: if ready {
+                "Ready"
+            } else {
+                "Database unavailable"
+            }
+            .to_string(),
+            payload: (),
+        }),
+    )
+}
+
+/// Resolve once the process is asked to stop (`SIGTERM` or Ctrl+C)
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    println!("Shutting down...");
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok(); // load .env
+    let cli = Cli::parse();
+
+    // load config
+    let path = cli.config.as_deref().unwrap_or("starstraw.toml");
+    let config: Config = match std::fs::read_to_string(path) {
+        Ok(s) => match toml::from_str(&s) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("error: invalid config file \"{path}\": {e}");
+                std::process::exit(1);
+            }
+        },
+        // only fall back to defaults if no config file was asked for
+        Err(e) if cli.config.is_some() => {
+            eprintln!("error: can't read config file \"{path}\": {e}");
+            std::process::exit(1);
+        }
+        Err(_) => {
+            println!("No config file at \"{path}\", using defaults");
+            Config::default()
+        }
+    };
+
+    // create database
+    let database = if config.in_memory() {
+        println!("Using an in-memory database, nothing will be persisted");
+        Database::memory(config.server_options())
+    } else {
+        Database::new(config.database_options(), config.server_options()).await
+    };
+    if let Err(e) = database.init().await {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+
+    // nothing can be created with starstraw-admin in memory, so create the owner before
+    // anybody else can sign up
+    if config.in_memory() {
+        if let Some(ref owner) = database.config.owner {
+            if let Ok(id) = database
+                .create_profile(owner.clone(), &Actor::system())
+                .await
+            {
+                let _ = database.bootstrap_owner().await;
+                println!("Created the owner \"{owner}\", account key: {id}");
+            }
+        }
+    }
+
+    database.spawn_key_rotation();
+    database.spawn_skill_expiry();
+
+    // create app
+    let routes = starstraw::api::routes(database.clone());
+    let prefix = config.server.prefix.trim_end_matches('/');
+
+    let mut app = Router::new()
+        .route("/health", get(health_request))
+        .route("/ready", get(ready_request))
+        .with_state(database.clone());
+
+    app = if prefix.is_empty() {
+        app.merge(routes)
+    } else {
+        app.nest(prefix, routes)
+    };
+
+    app = app.fallback(starstraw::api::not_found);
+
+    if let Some(cors) = config.cors() {
+        app = app.layer(cors);
+    }
+
+    let listener = match tokio::net::TcpListener::bind(&config.server.bind).await {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("error: failed to bind to {}: {e}", config.server.bind);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Starting server at http://{}!", config.server.bind);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
+}
@@ -1,45 +1,277 @@
 use crate::model::SkillSet;
-use crate::model::{Profile, ProfileMetadata, Skill, SkillName, StrawError};
+use crate::model::{
+    Actor, ApiKey, ApiKeyScope, AuditAction, AuditEntry, AuditQuery, OAuthClient, OAuthCode,
+    OAuthToken, Profile, ProfileMetadata, SecondaryToken, Session, SigningKey, Skill, SkillGrant,
+    SkillManager, SkillName, SkillType, StrawError,
+};
 
+use crate::memory::MemoryStore;
+use crate::migration::{self, Conversion, Migration, MigrationError};
+use crate::registry::SkillRegistry;
+use crate::store::{self, ProfileStore};
 use dorsal::query as sqlquery;
 use dorsal::utility;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
 
 pub type Result<T> = std::result::Result<T, StrawError>;
 
+/// Write an [`AuditEntry`] in the given transaction, returning [`StrawError::Other`]
+/// from the calling function if it can't be written
+macro_rules! audit {
+    ($store:expr, $tx:expr, $entry:expr) => {
+        let (query, values) = $store.audit_statement(&$entry);
+        let mut q = sqlquery(query);
+
+        for value in &values {
+            q = q.bind::<&String>(value);
+        }
+
+        if q.execute(&mut *$tx).await.is_err() {
+            return Err(StrawError::Other);
+        }
+    };
+}
+
+/// How long a handoff code can be exchanged for after it was created (in milliseconds)
+pub const HANDOFF_CODE_LIFETIME: u128 = 60 * 1000;
+/// How long an OAuth authorization code can be exchanged for after it was created (in milliseconds)
+pub const OAUTH_CODE_LIFETIME: u128 = 60 * 1000;
+/// How long an OAuth access token is valid for (in milliseconds)
+pub const OAUTH_TOKEN_LIFETIME: u128 = 60 * 60 * 1000;
+
+/// A place in the request a token can be read from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenSource {
+    /// The cookie named by [`CookieOptions::name`] (`__Secure-Token` by default)
+    Cookie,
+    /// The `Authorization: Bearer <token>` header
+    Bearer,
+    /// The `X-Straw-Token` header
+    Header,
+}
+
+/// Who is allowed to create new profiles through `/start`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationPolicy {
+    /// Anybody can create a profile
+    Open,
+    /// Nobody can create a profile, every profile must be created by an operator
+    /// (`starstraw-admin create`)
+    Closed,
+}
+
+/// Attributes of the cookie which stores the session token
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CookieOptions {
+    /// The name of the cookie
+    ///
+    /// Names starting with `__Secure-` are only accepted by browsers if `secure` is set.
+    pub name: String,
+    /// The domain the cookie is sent to, the cookie is host-only if `None`
+    pub domain: Option<String>,
+    pub path: String,
+    /// Only send the cookie over HTTPS
+    pub secure: bool,
+    /// The `SameSite` attribute (`"Strict"`, `"Lax"` or `"None"`)
+    pub same_site: String,
+    /// How long the cookie is kept for (in seconds)
+    pub max_age: u64,
+}
+
+impl CookieOptions {
+    /// Build the `Set-Cookie` value which stores the given token
+    pub fn set(&self, token: &str) -> String {
+        self.build(token, self.max_age)
+    }
+
+    /// Build the `Set-Cookie` value which removes the cookie
+    pub fn remove(&self) -> String {
+        self.build("refresh", 0)
+    }
+
+    fn build(&self, value: &str, max_age: u64) -> String {
+        let mut cookie = format!(
+            "{}={}; SameSite={}; Path={}; HttpOnly; Max-Age={}",
+            self.name, value, self.same_site, self.path, max_age
+        );
+
+        if let Some(ref domain) = self.domain {
+            cookie.push_str(&format!("; Domain={domain}"));
+        }
+
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+
+        cookie
+    }
+}
+
+impl Default for CookieOptions {
+    fn default() -> Self {
+        Self {
+            name: crate::extract::TOKEN_COOKIE.to_string(),
+            domain: None,
+            path: "/".to_string(),
+            secure: true,
+            same_site: "Lax".to_string(),
+            max_age: 60 * 60 * 24 * 365,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
-pub struct ServerOptions {}
+pub struct ServerOptions {
+    /// URL prefixes the `/travel` callback is allowed to redirect to
+    ///
+    /// `"/"` allows any path on the same origin.
+    pub redirect_allowlist: Vec<String>,
+    /// The public URL the API routes are served at, used as the OAuth and JWT issuer
+    pub issuer: String,
+    /// How long issued JWTs are valid for (in milliseconds)
+    pub jwt_lifetime: u128,
+    /// How long a JWT signing key is used for before it is replaced (in milliseconds)
+    ///
+    /// Replaced keys are still published until every token they signed has expired.
+    pub key_rotation_interval: u128,
+    /// Where tokens are read from, in order of precedence
+    pub token_sources: Vec<TokenSource>,
+    /// The username of the profile which is granted the "God" title once, so a new
+    /// deployment can have an administrator ([`Database::bootstrap_owner`])
+    ///
+    /// The title is only granted by `starstraw-admin create` and `starstraw-admin
+    /// bootstrap`, never when somebody signs up with this username.
+    pub owner: Option<String>,
+    /// The cookie which stores the session token
+    pub cookie: CookieOptions,
+    /// Who is allowed to create new profiles
+    pub registration: RegistrationPolicy,
+    /// Every skill which can be granted
+    pub skills: SkillRegistry,
+    /// Read the client IP from the `X-Forwarded-For` and `X-Real-IP` headers
+    ///
+    /// Only enable this behind a reverse proxy which sets these headers, any client
+    /// can send them otherwise.
+    pub trusted_proxy: bool,
+}
 
 impl ServerOptions {
     /// Enable all options
     pub fn truthy() -> Self {
-        Self {}
+        Self {
+            token_sources: vec![
+                TokenSource::Cookie,
+                TokenSource::Bearer,
+                TokenSource::Header,
+            ],
+            registration: RegistrationPolicy::Open,
+            ..Default::default()
+        }
+    }
+
+    /// Check if profiles can be created through `/start` by `registration`
+    pub fn registration_allowed(&self) -> bool {
+        self.registration == RegistrationPolicy::Open
+    }
+
+    /// Check if the given redirect target is allowed by `redirect_allowlist`
+    pub fn redirect_allowed(&self, target: &str) -> bool {
+        // protocol-relative URLs would leave the origin, and browsers strip tabs and
+        // newlines before parsing ("/\t/evil.com" is "//evil.com")
+        if target.starts_with("//")
+            | target.starts_with("/\\")
+            | target.chars().any(|c| c.is_control() | c.is_whitespace())
+        {
+            return false;
+        }
+
+        self.redirect_allowlist.iter().any(|allowed| {
+            if allowed.is_empty() {
+                return false;
+            }
+
+            // the allowed prefix must end at a path boundary
+            match target.strip_prefix(allowed.as_str()) {
+                Some(rest) => {
+                    allowed.ends_with('/')
+                        | rest.is_empty()
+                        | rest.starts_with('/')
+                        | rest.starts_with('?')
+                }
+                None => false,
+            }
+        })
     }
 }
 
 impl Default for ServerOptions {
     fn default() -> Self {
-        Self {}
+        Self {
+            redirect_allowlist: vec!["/".to_string()],
+            issuer: "http://localhost:8080".to_string(),
+            jwt_lifetime: 15 * 60 * 1000,
+            key_rotation_interval: 7 * 24 * 60 * 60 * 1000,
+            token_sources: vec![
+                TokenSource::Cookie,
+                TokenSource::Bearer,
+                TokenSource::Header,
+            ],
+            owner: None,
+            cookie: CookieOptions::default(),
+            registration: RegistrationPolicy::Open,
+            skills: SkillRegistry::default(),
+            // headers from clients are never trusted unless asked for
+            trusted_proxy: false,
+        }
     }
 }
 
 /// Database connector
+///
+/// Derefs to its [`ProfileStore`], so every storage operation can be called on it directly.
 #[derive(Clone)]
 pub struct Database {
-    pub base: dorsal::StarterDatabase,
+    pub store: Arc<dyn ProfileStore>,
     pub config: ServerOptions,
+    /// The migrations applied by [`Database::init`], starting with [`migration::builtin`]
+    pub migrations: Vec<Migration>,
+}
+
+impl Deref for Database {
+    type Target = dyn ProfileStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.store.as_ref()
+    }
 }
 
 impl Database {
-    /// Create a new [`Database`]
+    /// Create a new [`Database`] backed by a [`SqlStore`]
     pub async fn new(
         database_options: dorsal::DatabaseOpts,
         server_options: ServerOptions,
     ) -> Self {
-        let base = dorsal::StarterDatabase::new(database_options).await;
+        let store = SqlStore::new(database_options, server_options.clone()).await;
+        Self::with_store(store, server_options)
+    }
+
+    /// Create a new [`Database`] backed by a [`MemoryStore`], everything is lost when
+    /// the process exits
+    pub fn memory(server_options: ServerOptions) -> Self {
+        Self::with_store(MemoryStore::new(server_options.clone()), server_options)
+    }
 
+    /// Create a new [`Database`] backed by the given [`ProfileStore`]
+    pub fn with_store(store: impl ProfileStore + 'static, server_options: ServerOptions) -> Self {
         Self {
-            base: base.clone(),
+            store: Arc::new(store),
             config: server_options,
+            migrations: migration::builtin(),
         }
     }
 
@@ -61,303 +293,2604 @@ impl Database {
         }
     }
 
-    /// Init database
-    pub async fn init(&self) {
-        // create tables
-        let c = &self.base.db.client;
+    /// Pull the bootstrap owner username ([`ServerOptions::owner`]) from env (`STRAW_OWNER`)
+    pub fn env_owner() -> Option<String> {
+        match std::env::var("STRAW_OWNER") {
+            Ok(v) if !v.is_empty() => Option::Some(v),
+            _ => Option::None,
+        }
+    }
 
-        let _ = sqlquery(
-            "CREATE TABLE IF NOT EXISTS \"sr_profiles\" (
-                id       TEXT,
-                username TEXT,
-                metadata TEXT,
-                joined   TEXT,
-                skills   TEXT
-            )",
-        )
-        .execute(c)
-        .await;
+    /// Append a [`Migration`] which is applied by [`Database::init`] after every
+    /// migration added before it
+    pub fn add_migration(&mut self, migration: Migration) {
+        self.migrations.push(migration);
     }
 
-    // profiles
+    /// Apply every [`Migration`] which hasn't been applied yet, in order. Returns the
+    /// `id` of each applied migration ([`ProfileStore::apply_migrations`])
+    pub async fn migrate(&self) -> std::result::Result<Vec<String>, MigrationError> {
+        self.store.apply_migrations(&self.migrations).await
+    }
 
-    // GET
-    /// Get a [`Profile`] by their hashed ID
+    /// Init database
     ///
-    /// # Arguments:
-    /// * `hashed` - `String` of the profile's hashed ID
-    pub async fn get_profile_by_hashed(&self, hashed: String) -> Result<Profile> {
-        // fetch from database
-        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
-            "SELECT * FROM \"sr_profiles\" WHERE \"id\" = ?"
-        } else {
-            "SELECT * FROM \"sr_profiles\" WHERE \"id\" = $1"
-        };
+    /// Applies migrations ([`Database::migrate`]) and makes sure a signing key exists.
+    pub async fn init(&self) -> std::result::Result<(), MigrationError> {
+        self.migrate().await?;
 
-        let c = &self.base.db.client;
-        let row = match sqlquery(query).bind::<&String>(&hashed).fetch_one(c).await {
-            Ok(u) => self.base.textify_row(u).data,
-            Err(_) => return Err(StrawError::Other),
-        };
+        // make sure we have a signing key
+        let _ = self.rotate_signing_keys().await;
+        Ok(())
+    }
 
-        // return
-        Ok(Profile {
-            id: row.get("id").unwrap().to_string(),
-            username: row.get("username").unwrap().to_string(),
-            metadata: match serde_json::from_str(row.get("metadata").unwrap()) {
-                Ok(m) => m,
-                Err(_) => return Err(StrawError::ValueError),
-            },
-            skills: match serde_json::from_str(row.get("skills").unwrap()) {
-                Ok(m) => m,
-                Err(_) => return Err(StrawError::ValueError),
-            },
-            joined: row.get("joined").unwrap().parse::<u128>().unwrap(),
+    /// Spawn a task which calls [`ProfileStore::rotate_signing_keys`] periodically
+    pub fn spawn_key_rotation(&self) -> tokio::task::JoinHandle<()> {
+        let database = self.clone();
+        let period = self
+            .config
+            .key_rotation_interval
+            .clamp(1000, 60 * 60 * 1000);
+
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_millis(period as u64));
+
+            loop {
+                interval.tick().await;
+                let _ = database.rotate_signing_keys().await;
+            }
         })
     }
 
-    /// Get a user by their unhashed ID (hashes ID and then calls [`Database::get_profile_by_hashed()`])
+    /// Remove every expired skill from storage ([`SkillManager::expire`]). Returns the
+    /// number of profiles which were updated
     ///
-    /// # Arguments:
-    /// * `unhashed` - `String` of the user's unhashed ID
-    pub async fn get_profile_by_unhashed(&self, unhashed: String) -> Result<Profile> {
-        match self
-            .get_profile_by_hashed(utility::hash(unhashed.clone()))
-            .await
-        {
-            Ok(r) => Ok(r),
-            Err(_) => self.get_profile_by_unhashed_st(unhashed).await,
+    /// Profiles which are edited while they're being updated are skipped, and picked
+    /// up again by the next call.
+    pub async fn expire_skills(&self) -> Result<usize> {
+        let now = utility::unix_epoch_timestamp();
+        let mut updated = 0;
+
+        for profile in self.get_profiles_with_expired_skills(now).await? {
+            let mut manager = SkillManager::from(&profile);
+
+            if !manager.expire() {
+                continue;
+            }
+
+            let (skills, grants) = manager.into_parts();
+            match self
+                .edit_profile_skills_by_name(
+                    profile.username.clone(),
+                    skills,
+                    grants,
+                    profile.version,
+                    &Actor::system(),
+                    AuditAction::Expire,
+                )
+                .await
+            {
+                Ok(_) => updated += 1,
+                Err(StrawError::Conflict) => continue,
+                Err(e) => return Err(e),
+            }
         }
+
+        Ok(updated)
     }
 
-    /// Get a user by their unhashed secondary token
-    ///
-    /// # Arguments:
-    /// * `unhashed` - `String` of the user's unhashed secondary token
-    pub async fn get_profile_by_unhashed_st(&self, unhashed: String) -> Result<Profile> {
-        // fetch from database
-        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
-            "SELECT * FROM \"sr_profiles\" WHERE \"metadata\" LIKE ?"
-        } else {
-            "SELECT * FROM \"sr_profiles\" WHERE \"metadata\" LIKE $1"
-        };
+    /// Spawn a task which calls [`Database::expire_skills`] every minute
+    pub fn spawn_skill_expiry(&self) -> tokio::task::JoinHandle<()> {
+        let database = self.clone();
 
-        let c = &self.base.db.client;
-        let row = match sqlquery(query)
-            .bind::<&String>(&format!(
-                "%\"secondary_token\":\"{}\"%",
-                utility::hash(unhashed)
-            ))
-            .fetch_one(c)
-            .await
-        {
-            Ok(r) => self.base.textify_row(r).data,
-            Err(_) => return Err(StrawError::Other),
-        };
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+                let _ = database.expire_skills().await;
+            }
+        })
+    }
+}
+
+/// [`ProfileStore`] backed by SQL (sqlite, postgres or mysql) and a cache
+#[derive(Clone)]
+pub struct SqlStore {
+    pub base: dorsal::StarterDatabase,
+    pub config: ServerOptions,
+}
+
+impl SqlStore {
+    /// Create a new [`SqlStore`]
+    pub async fn new(
+        database_options: dorsal::DatabaseOpts,
+        server_options: ServerOptions,
+    ) -> Self {
+        Self {
+            base: dorsal::StarterDatabase::new(database_options).await,
+            config: server_options,
+        }
+    }
+
+    /// Build a [`Profile`] from a textified `sr_profiles` row, loading its skills
+    async fn profile_from_row(&self, row: &HashMap<String, String>) -> Result<Profile> {
+        let id = row.get("id").unwrap().to_string();
+        let (skills, grants) = self.get_skills(&id).await?;
 
-        // return
         Ok(Profile {
-            id: row.get("id").unwrap().to_string(),
+            skills,
+            grants,
+            id,
+            uid: row.get("uid").cloned().unwrap_or_default(),
             username: row.get("username").unwrap().to_string(),
             metadata: match serde_json::from_str(row.get("metadata").unwrap()) {
                 Ok(m) => m,
                 Err(_) => return Err(StrawError::ValueError),
             },
-            skills: match serde_json::from_str(row.get("metadata").unwrap()) {
-                Ok(m) => m,
-                Err(_) => return Err(StrawError::ValueError),
-            },
             joined: row.get("joined").unwrap().parse::<u128>().unwrap(),
+            version: row
+                .get("version")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0),
         })
     }
 
-    /// Get a user by their username
-    ///
-    /// # Arguments:
-    /// * `username` - `String` of the user's username
-    pub async fn get_profile_by_username(&self, mut username: String) -> Result<Profile> {
-        username = username.to_lowercase();
+    /// Get the `skill_type` and `name` columns of a [`Skill`]
+    fn skill_columns(skill: &Skill) -> (String, String) {
+        let column = |v: serde_json::Value| v.as_str().unwrap_or_default().to_string();
 
-        // check in cache
-        let cached = self
-            .base
-            .cachedb
-            .get(format!("sr_profile:{}", username))
-            .await;
+        (
+            column(serde_json::to_value(&skill.0 .0).unwrap()),
+            column(serde_json::to_value(&skill.0 .1).unwrap()),
+        )
+    }
 
-        if cached.is_some() {
-            return Ok(serde_json::from_str::<Profile>(cached.unwrap().as_str()).unwrap());
+    /// Build an [`OAuthCode`] from a textified `sr_oauth_codes` row
+    fn oauth_code_from_row(row: &HashMap<String, String>) -> OAuthCode {
+        OAuthCode {
+            code: row.get("code").unwrap().to_string(),
+            client: row.get("client").unwrap().to_string(),
+            profile: row.get("profile").unwrap().to_string(),
+            redirect_uri: row.get("redirect_uri").unwrap().to_string(),
+            scope: row.get("scope").unwrap().to_string(),
+            challenge: row.get("challenge").unwrap().to_string(),
+            nonce: row.get("nonce").unwrap().to_string(),
+            created: row.get("created").unwrap().parse::<u128>().unwrap(),
         }
+    }
 
-        // ...
-        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
-            "SELECT * FROM \"sr_profiles\" WHERE \"username\" = ?"
-        } else {
-            "SELECT * FROM \"sr_profiles\" WHERE \"username\" = $1"
-        };
+    /// Get the `action` column of an [`AuditAction`]
+    fn audit_action_column(action: &AuditAction) -> String {
+        match serde_json::to_value(action) {
+            Ok(serde_json::Value::String(s)) => s,
+            _ => String::new(),
+        }
+    }
 
-        let c = &self.base.db.client;
-        let row = match sqlquery(query)
-            .bind::<&String>(&username)
-            .fetch_one(c)
-            .await
-        {
-            Ok(r) => self.base.textify_row(r).data,
-            Err(_) => return Err(StrawError::NotFound),
+    /// Build an [`AuditEntry`] from a textified `sr_audit` row
+    fn audit_from_row(row: &HashMap<String, String>) -> Result<AuditEntry> {
+        let column = |name: &str| row.get(name).cloned().unwrap_or_default();
+        let skills = |name: &str| -> Result<Option<SkillSet>> {
+            match row.get(name).map(|s| s.as_str()) {
+                None | Some("") => Ok(None),
+                Some(s) => match serde_json::from_str(s) {
+                    Ok(s) => Ok(Some(s)),
+                    Err(_) => Err(StrawError::ValueError),
+                },
+            }
         };
 
-        // store in cache
-        let user = Profile {
-            id: row.get("id").unwrap().to_string(),
-            username: row.get("username").unwrap().to_string(),
-            metadata: match serde_json::from_str(row.get("metadata").unwrap()) {
-                Ok(m) => m,
-                Err(_) => return Err(StrawError::ValueError),
-            },
-            skills: match serde_json::from_str(row.get("skills").unwrap()) {
-                Ok(m) => m,
+        Ok(AuditEntry {
+            id: column("id"),
+            actor: column("actor"),
+            target: column("target"),
+            action: match serde_json::from_value(serde_json::Value::String(column("action"))) {
+                Ok(a) => a,
                 Err(_) => return Err(StrawError::ValueError),
             },
-            joined: row.get("joined").unwrap().parse::<u128>().unwrap(),
-        };
-
-        self.base
-            .cachedb
-            .set(
-                format!("sr_profile:{}", username),
-                serde_json::to_string::<Profile>(&user).unwrap(),
-            )
-            .await;
-
-        // return
-        Ok(user)
+            before: skills("skills_before")?,
+            after: skills("skills_after")?,
+            timestamp: column("created").parse::<u128>().unwrap_or(0),
+            ip: column("ip"),
+            reason: Some(column("reason")).filter(|r| !r.is_empty()),
+        })
     }
 
-    // SET
-    /// Create a new user given their username. Returns their hashed ID
-    ///
-    /// # Arguments:
-    /// * `username` - `String` of the user's `username`
-    pub async fn create_profile(&self, username: String) -> Result<String> {
-        // make sure user doesn't already exists
-        if let Ok(_) = &self.get_profile_by_username(username.clone()).await {
-            return Err(StrawError::MustBeUnique);
+    /// Get the statement which inserts an [`AuditEntry`], along with the values to bind
+    fn audit_statement(&self, entry: &AuditEntry) -> (&'static str, Vec<String>) {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "INSERT INTO \"sr_audit\" VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        } else {
+            "INSERT INTO \"sr_audit\" VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
         };
 
-        // check username
-        let regex = regex::RegexBuilder::new("^[\\w\\_\\-\\.\\!]+$")
-            .multi_line(true)
-            .build()
-            .unwrap();
+        let skills = |s: &Option<SkillSet>| match s {
+            Some(s) => serde_json::to_string(s).unwrap_or_default(),
+            None => String::new(),
+        };
 
-        if regex.captures(&username).iter().len() < 1 {
-            return Err(StrawError::ValueError);
-        }
+        (
+            query,
+            vec![
+                entry.id.clone(),
+                entry.actor.clone(),
+                entry.target.clone(),
+                Self::audit_action_column(&entry.action),
+                skills(&entry.before),
+                skills(&entry.after),
+                entry.timestamp.to_string(),
+                entry.ip.clone(),
+                entry.reason.clone().unwrap_or_default(),
+            ],
+        )
+    }
 
-        if (username.len() < 2) | (username.len() > 500) {
-            return Err(StrawError::ValueError);
-        }
+    /// Build a [`Skill`] from a textified `sr_skills` row
+    fn skill_from_row(row: &HashMap<String, String>) -> Result<Skill> {
+        let column = |name: &str| serde_json::Value::String(row.get(name).unwrap().to_string());
 
-        // ...
-        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
-            "INSERT INTO \"sr_profiles\" VALUES (?, ?, ?, ?, ?)"
-        } else {
-            "INSERT INTO \"sr_profiles\" VALUES ($1, $2, $3, $4, $5)"
+        let skill_type: SkillType = match serde_json::from_value(column("skill_type")) {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::ValueError),
         };
 
-        let user_id_unhashed: String = dorsal::utility::uuid();
-        let user_id_hashed: String = dorsal::utility::hash(user_id_unhashed.clone());
-        let timestamp = utility::unix_epoch_timestamp().to_string();
+        let name: SkillName = match serde_json::from_value(column("name")) {
+            Ok(n) => n,
+            Err(_) => return Err(StrawError::ValueError),
+        };
 
-        let c = &self.base.db.client;
-        match sqlquery(query)
-            .bind::<&String>(&user_id_hashed)
-            .bind::<&String>(&username.to_lowercase())
-            .bind::<&String>(
-                &serde_json::to_string::<ProfileMetadata>(&ProfileMetadata {
-                    secondary_token: String::new(),
-                })
-                .unwrap(),
-            )
-            .bind::<&String>(&timestamp)
-            .bind::<&String>(
-                &serde_json::to_string::<Vec<Skill>>(&[SkillName::Normal.into()].to_vec()).unwrap(),
-            )
-            .execute(c)
-            .await
-        {
-            Ok(_) => Ok(user_id_unhashed),
-            Err(_) => Err(StrawError::Other),
+        match row.get("value").unwrap().parse::<f32>() {
+            Ok(v) => Ok(((skill_type, name), v)),
+            Err(_) => Err(StrawError::ValueError),
         }
     }
+}
 
-    /// Update a [`Profile`]'s metadata by its `username`
-    pub async fn edit_profile_metadata_by_name(
+#[async_trait::async_trait]
+impl ProfileStore for SqlStore {
+    /// Each migration runs in its own transaction and is recorded in `sr_migrations`.
+    /// Stops at the first migration which fails.
+    async fn apply_migrations(
         &self,
-        name: String,
-        metadata: ProfileMetadata,
-    ) -> Result<()> {
-        // make sure user exists
-        if let Err(e) = self.get_profile_by_username(name.clone()).await {
-            return Err(e);
+        migrations: &[Migration],
+    ) -> std::result::Result<Vec<String>, MigrationError> {
+        let c = &self.base.db.client;
+        if let Err(e) = sqlquery(
+            "CREATE TABLE IF NOT EXISTS \"sr_migrations\" (
+                id      VARCHAR(255) PRIMARY KEY,
+                applied TEXT
+            )",
+        )
+        .execute(c)
+        .await
+        {
+            return Err(MigrationError::new("", e));
+        }
+
+        // get applied migrations
+        let applied: Vec<String> = match sqlquery("SELECT * FROM \"sr_migrations\"")
+            .fetch_all(c)
+            .await
+        {
+            Ok(rows) => rows
+                .into_iter()
+                .map(|r| {
+                    self.base
+                        .textify_row(r)
+                        .data
+                        .get("id")
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .collect(),
+            Err(e) => return Err(MigrationError::new("", e)),
         };
 
-        // update user
-        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
-            "UPDATE \"sr_profiles\" SET \"metadata\" = ? WHERE \"username\" = ?"
+        // apply missing migrations
+        let record: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "INSERT INTO \"sr_migrations\" VALUES (?, ?)"
         } else {
-            "UPDATE \"sr_profiles\" SET (\"metadata\") = ($1) WHERE \"username\" = $2"
+            "INSERT INTO \"sr_migrations\" VALUES ($1, $2)"
         };
 
-        let c = &self.base.db.client;
-        let meta = &serde_json::to_string(&metadata).unwrap();
-        match sqlquery(query)
-            .bind::<&String>(meta)
-            .bind::<&String>(&name)
-            .execute(c)
-            .await
+        let mut out = Vec::new();
+
+        for migration in migrations {
+            if applied.contains(&migration.id) {
+                continue;
+            }
+
+            let mut tx = match c.begin().await {
+                Ok(t) => t,
+                Err(e) => return Err(MigrationError::new(&migration.id, e)),
+            };
+
+            if migration.conversion == Some(Conversion::UniqueUsernames) {
+                // the unique index would fail with an error which doesn't say which
+                let rows = match sqlquery(
+                    "SELECT \"username\" FROM \"sr_profiles\" GROUP BY \"username\" HAVING COUNT(*) > 1",
+                )
+                .fetch_all(&mut *tx)
+                .await
+                {
+                    Ok(r) => r,
+                    Err(e) => return Err(MigrationError::new(&migration.id, e)),
+                };
+
+                if !rows.is_empty() {
+                    let usernames: Vec<String> = rows
+                        .into_iter()
+                        .map(|row| {
+                            let row = self.base.textify_row(row).data;
+                            format!("\"{}\"", row.get("username").cloned().unwrap_or_default())
+                        })
+                        .collect();
+
+                    return Err(MigrationError::new(
+                        &migration.id,
+                        format!(
+                            "more than one profile has the username {}, rename or delete the duplicates and migrate again",
+                            usernames.join(", ")
+                        ),
+                    ));
+                }
+            }
+
+            if migration.conversion == Some(Conversion::SkillsToTable) {
+                // copy every skill set into sr_skills, keeping its order
+                let rows =
+                    match sqlquery("SELECT \"id\", \"joined\", \"skills\" FROM \"sr_profiles\"")
+                        .fetch_all(&mut *tx)
+                        .await
+                    {
+                        Ok(r) => r,
+                        Err(e) => return Err(MigrationError::new(&migration.id, e)),
+                    };
+
+                let insert: &str =
+                    if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                        "INSERT INTO \"sr_skills\" VALUES (?, ?, ?, ?, ?, ?, ?)"
+                    } else {
+                        "INSERT INTO \"sr_skills\" VALUES ($1, $2, $3, $4, $5, $6, $7)"
+                    };
+
+                for row in rows {
+                    let row = self.base.textify_row(row).data;
+                    let id = row.get("id").cloned().unwrap_or_default();
+                    let joined = row.get("joined").cloned().unwrap_or_default();
+
+                    let skills: SkillSet =
+                        match serde_json::from_str(row.get("skills").map_or("[]", |s| s)) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                return Err(MigrationError::new(
+                                    &migration.id,
+                                    format!("profile \"{id}\" has invalid skills: {e}"),
+                                ))
+                            }
+                        };
+
+                    for (i, skill) in skills.iter().enumerate() {
+                        let (skill_type, name) = Self::skill_columns(skill);
+
+                        if let Err(e) = sqlquery(insert)
+                            .bind::<&String>(&id)
+                            .bind::<&String>(&i.to_string())
+                            .bind::<&String>(&skill_type)
+                            .bind::<&String>(&name)
+                            .bind::<&String>(&skill.1.to_string())
+                            .bind::<&String>(&joined)
+                            .bind::<&str>("")
+                            .execute(&mut *tx)
+                            .await
+                        {
+                            return Err(MigrationError::new(&migration.id, e));
+                        }
+                    }
+                }
+            }
+
+            if migration.conversion == Some(Conversion::ProfileUids) {
+                let rows = match sqlquery("SELECT \"id\" FROM \"sr_profiles\"")
+                    .fetch_all(&mut *tx)
+                    .await
+                {
+                    Ok(r) => r,
+                    Err(e) => return Err(MigrationError::new(&migration.id, e)),
+                };
+
+                let update: &str =
+                    if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                        "UPDATE \"sr_profiles\" SET \"uid\" = ? WHERE \"id\" = ?"
+                    } else {
+                        "UPDATE \"sr_profiles\" SET \"uid\" = $1 WHERE \"id\" = $2"
+                    };
+
+                for row in rows {
+                    let row = self.base.textify_row(row).data;
+                    let id = row.get("id").cloned().unwrap_or_default();
+
+                    if let Err(e) = sqlquery(update)
+                        .bind::<&String>(&utility::uuid())
+                        .bind::<&String>(&id)
+                        .execute(&mut *tx)
+                        .await
+                    {
+                        return Err(MigrationError::new(&migration.id, e));
+                    }
+                }
+            }
+
+            if migration.conversion == Some(Conversion::SecondaryTokensToTable) {
+                // columns are named, since statements are cached across schema changes
+                let rows =
+                    match sqlquery("SELECT \"id\", \"joined\", \"metadata\" FROM \"sr_profiles\"")
+                        .fetch_all(&mut *tx)
+                        .await
+                    {
+                        Ok(r) => r,
+                        Err(e) => return Err(MigrationError::new(&migration.id, e)),
+                    };
+
+                let (insert, update): (&str, &str) =
+                    if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                        (
+                            "INSERT INTO \"sr_secondary_tokens\" VALUES (?, ?, ?, ?)",
+                            "UPDATE \"sr_profiles\" SET \"metadata\" = ? WHERE \"id\" = ?",
+                        )
+                    } else {
+                        (
+                            "INSERT INTO \"sr_secondary_tokens\" VALUES ($1, $2, $3, $4)",
+                            "UPDATE \"sr_profiles\" SET \"metadata\" = $1 WHERE \"id\" = $2",
+                        )
+                    };
+
+                for row in rows {
+                    let row = self.base.textify_row(row).data;
+                    let id = row.get("id").cloned().unwrap_or_default();
+                    let joined = row.get("joined").cloned().unwrap_or_default();
+
+                    let mut metadata: serde_json::Value =
+                        match serde_json::from_str(row.get("metadata").map_or("{}", |s| s)) {
+                            Ok(m) => m,
+                            Err(e) => {
+                                return Err(MigrationError::new(
+                                    &migration.id,
+                                    format!("profile \"{id}\" has invalid metadata: {e}"),
+                                ))
+                            }
+                        };
+
+                    // (name, hashed token, created)
+                    let mut tokens: Vec<(String, String, String)> = Vec::new();
+
+                    if let Some(object) = metadata.as_object_mut() {
+                        if let Some(value) = object.remove("secondary_tokens") {
+                            let list: Vec<SecondaryToken> =
+                                serde_json::from_value(value).unwrap_or_default();
+
+                            for t in list {
+                                if tokens.iter().any(|(name, _, _)| name == &t.name) {
+                                    continue;
+                                }
+
+                                tokens.push((t.name, t.token, t.created.to_string()));
+                            }
+                        }
+
+                        // the legacy token has no name, so it gets one which isn't taken
+                        if let Some(serde_json::Value::String(token)) =
+                            object.remove("secondary_token")
+                        {
+                            if !token.is_empty() {
+                                let taken = |name: &str| tokens.iter().any(|(n, _, _)| n == name);
+
+                                let mut name = "legacy".to_string();
+                                let mut i = 1;
+
+                                while taken(&name) {
+                                    name = format!("legacy-{i}");
+                                    i += 1;
+                                }
+
+                                tokens.push((name, token, joined.clone()));
+                            }
+                        }
+                    }
+
+                    for (name, token, created) in tokens {
+                        if let Err(e) = sqlquery(insert)
+                            .bind::<&String>(&token)
+                            .bind::<&String>(&id)
+                            .bind::<&String>(&name)
+                            .bind::<&String>(&created)
+                            .execute(&mut *tx)
+                            .await
+                        {
+                            return Err(MigrationError::new(&migration.id, e));
+                        }
+                    }
+
+                    if let Err(e) = sqlquery(update)
+                        .bind::<&String>(&metadata.to_string())
+                        .bind::<&String>(&id)
+                        .execute(&mut *tx)
+                        .await
+                    {
+                        return Err(MigrationError::new(&migration.id, e));
+                    }
+                }
+            }
+
+            for statement in migration.statements(&self.base.db._type) {
+                if let Err(e) = sqlquery(statement).execute(&mut *tx).await {
+                    return Err(MigrationError::new(&migration.id, e));
+                }
+            }
+
+            if let Err(e) = sqlquery(record)
+                .bind::<&String>(&migration.id)
+                .bind::<&String>(&utility::unix_epoch_timestamp().to_string())
+                .execute(&mut *tx)
+                .await
+            {
+                return Err(MigrationError::new(&migration.id, e));
+            }
+
+            if let Err(e) = tx.commit().await {
+                return Err(MigrationError::new(&migration.id, e));
+            }
+
+            out.push(migration.id.clone());
+        }
+
+        Ok(out)
+    }
+
+    async fn ping(&self) -> Result<()> {
+        match sqlquery("SELECT 1").execute(&self.base.db.client).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(StrawError::Other),
+        }
+    }
+
+    async fn bootstrap_owner(&self) -> Result<bool> {
+        let owner = match self.config.owner {
+            Some(ref o) => o.to_lowercase(),
+            None => return Ok(false),
+        };
+
+        let profile = match self.get_profile_by_username(owner.clone()).await {
+            Ok(p) => p,
+            Err(_) => return Ok(false),
+        };
+
+        // ...
+        let (claim, bump, delete, insert): (&str, &str, &str, &str) = if self.base.db._type
+            == "mysql"
+        {
+            (
+                "INSERT INTO \"sr_state\" (\"name\", \"value\") SELECT ?, ? FROM DUAL WHERE NOT EXISTS (SELECT 1 FROM \"sr_state\" WHERE \"name\" = ?)",
+                "UPDATE \"sr_profiles\" SET \"version\" = ? WHERE \"id\" = ? AND \"version\" = ?",
+                "DELETE FROM \"sr_skills\" WHERE \"profile\" = ? AND \"skill_type\" = ?",
+                "INSERT INTO \"sr_skills\" (\"profile\", \"position\", \"skill_type\", \"name\", \"value\", \"granted_at\", \"granted_by\") VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+        } else if self.base.db._type == "sqlite" {
+            (
+                "INSERT INTO \"sr_state\" (\"name\", \"value\") SELECT ?, ? WHERE NOT EXISTS (SELECT 1 FROM \"sr_state\" WHERE \"name\" = ?)",
+                "UPDATE \"sr_profiles\" SET \"version\" = ? WHERE \"id\" = ? AND \"version\" = ?",
+                "DELETE FROM \"sr_skills\" WHERE \"profile\" = ? AND \"skill_type\" = ?",
+                "INSERT INTO \"sr_skills\" (\"profile\", \"position\", \"skill_type\", \"name\", \"value\", \"granted_at\", \"granted_by\") VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+        } else {
+            (
+                "INSERT INTO \"sr_state\" (\"name\", \"value\") SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM \"sr_state\" WHERE \"name\" = $3)",
+                "UPDATE \"sr_profiles\" SET \"version\" = $1 WHERE \"id\" = $2 AND \"version\" = $3",
+                "DELETE FROM \"sr_skills\" WHERE \"profile\" = $1 AND \"skill_type\" = $2",
+                "INSERT INTO \"sr_skills\" (\"profile\", \"position\", \"skill_type\", \"name\", \"value\", \"granted_at\", \"granted_by\") VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+        };
+
+        let title: Skill = SkillName::God.into();
+        let (skill_type, name) = Self::skill_columns(&title);
+        let timestamp = utility::unix_epoch_timestamp().to_string();
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // claim the bootstrap
+        let record = serde_json::json!({
+            "username": owner,
+            "granted": timestamp,
+        })
+        .to_string();
+
+        match sqlquery(claim)
+            .bind::<&str>("owner")
+            .bind::<&String>(&record)
+            .bind::<&str>("owner")
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    // already bootstrapped
+                    return Ok(false);
+                }
+            }
+            Err(_) => return Err(StrawError::Other),
+        }
+
+        // bump version, so anything read before the bootstrap can't be written back
+        match sqlquery(bump)
+            .bind::<&String>(&(profile.version + 1).to_string())
+            .bind::<&String>(&profile.id)
+            .bind::<&String>(&profile.version.to_string())
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(StrawError::Conflict);
+                }
+            }
+            Err(_) => return Err(StrawError::Other),
+        }
+
+        // grant title, replacing the current one (and any temporary title)
+        if sqlquery(delete)
+            .bind::<&String>(&profile.id)
+            .bind::<&String>(&skill_type)
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        // titles come before every other skill
+        if sqlquery(insert)
+            .bind::<&String>(&profile.id)
+            .bind::<&str>("-1")
+            .bind::<&String>(&skill_type)
+            .bind::<&String>(&name)
+            .bind::<&String>(&title.1.to_string())
+            .bind::<&String>(&timestamp)
+            .bind::<&str>("")
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        let mut after = profile.skills.clone();
+        after.retain(|s| s.0 .0 != SkillType::Title);
+        after.insert(0, title);
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            Actor::system()
+                .entry(&profile.username, AuditAction::Bootstrap)
+                .skills(&profile.skills, &after)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        self.base
+            .cachedb
+            .remove(format!("sr_profile:{}", profile.username))
+            .await;
+
+        Ok(true)
+    }
+
+    // profiles
+    async fn get_skills(&self, profile: &str) -> Result<(SkillSet, Vec<SkillGrant>)> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_skills\" WHERE \"profile\" = ?"
+        } else {
+            "SELECT * FROM \"sr_skills\" WHERE \"profile\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let rows = match sqlquery(query).bind::<&str>(profile).fetch_all(c).await {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // positions are stored as text, so they're sorted here
+        let mut skills: Vec<(i64, Skill)> = Vec::new();
+        let mut grants: Vec<SkillGrant> = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            let position = row.get("position").unwrap().parse::<i64>().unwrap_or(0);
+            let skill = Self::skill_from_row(&row)?;
+
+            grants.push(SkillGrant {
+                name: skill.0 .1.clone(),
+                granted_by: row.get("granted_by").cloned().unwrap_or_default(),
+                granted_at: row
+                    .get("granted_at")
+                    .and_then(|g| g.parse::<u128>().ok())
+                    .unwrap_or(0),
+                reason: row.get("reason").filter(|r| !r.is_empty()).cloned(),
+                expires_at: row.get("expires_at").and_then(|e| e.parse::<u128>().ok()),
+            });
+
+            skills.push((position, skill));
+        }
+
+        skills.sort_by_key(|s| s.0);
+        Ok((skills.into_iter().map(|s| s.1).collect(), grants))
+    }
+
+    // GET
+    async fn get_profile_by_hashed(&self, hashed: String) -> Result<Profile> {
+        // fetch from database
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_profiles\" WHERE \"id\" = ?"
+        } else {
+            "SELECT * FROM \"sr_profiles\" WHERE \"id\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query).bind::<&String>(&hashed).fetch_one(c).await {
+            Ok(u) => self.base.textify_row(u).data,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // return
+        self.profile_from_row(&row).await
+    }
+
+    async fn get_profile_by_uid(&self, uid: String) -> Result<Profile> {
+        // fetch from database
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_profiles\" WHERE \"uid\" = ?"
+        } else {
+            "SELECT * FROM \"sr_profiles\" WHERE \"uid\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query).bind::<&String>(&uid).fetch_one(c).await {
+            Ok(u) => self.base.textify_row(u).data,
+            Err(_) => return Err(StrawError::NotFound),
+        };
+
+        // return
+        self.profile_from_row(&row).await
+    }
+
+    async fn get_profile_by_unhashed_st(&self, unhashed: String) -> Result<Profile> {
+        // fetch from database
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_profiles\" WHERE \"id\" = (SELECT \"profile\" FROM \"sr_secondary_tokens\" WHERE \"token\" = ?)"
+        } else {
+            "SELECT * FROM \"sr_profiles\" WHERE \"id\" = (SELECT \"profile\" FROM \"sr_secondary_tokens\" WHERE \"token\" = $1)"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query)
+            .bind::<&String>(&utility::hash(unhashed))
+            .fetch_one(c)
+            .await
+        {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // return
+        self.profile_from_row(&row).await
+    }
+
+    async fn get_profile_by_username(&self, mut username: String) -> Result<Profile> {
+        username = username.to_lowercase();
+
+        // check in cache
+        let cached = self
+            .base
+            .cachedb
+            .get(format!("sr_profile:{}", username))
+            .await;
+
+        if cached.is_some() {
+            return Ok(serde_json::from_str::<Profile>(cached.unwrap().as_str()).unwrap());
+        }
+
+        // ...
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_profiles\" WHERE \"username\" = ?"
+        } else {
+            "SELECT * FROM \"sr_profiles\" WHERE \"username\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query)
+            .bind::<&String>(&username)
+            .fetch_one(c)
+            .await
+        {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::NotFound),
+        };
+
+        // store in cache
+        let user = self.profile_from_row(&row).await?;
+
+        self.base
+            .cachedb
+            .set(
+                format!("sr_profile:{}", username),
+                serde_json::to_string::<Profile>(&user).unwrap(),
+            )
+            .await;
+
+        // return
+        Ok(user)
+    }
+
+    async fn get_profiles(
+        &self,
+        query: String,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Profile>> {
+        let sql: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!("SELECT * FROM \"sr_profiles\" WHERE \"username\" LIKE ? ESCAPE '!' ORDER BY \"username\" LIMIT {limit} OFFSET {offset}")
+        } else {
+            format!("SELECT * FROM \"sr_profiles\" WHERE \"username\" LIKE $1 ESCAPE '!' ORDER BY \"username\" LIMIT {limit} OFFSET {offset}")
+        };
+
+        // escape wildcards so the query is matched literally
+        let pattern = format!(
+            "%{}%",
+            query
+                .to_lowercase()
+                .replace('!', "!!")
+                .replace('%', "!%")
+                .replace('_', "!_")
+        );
+
+        let c = &self.base.db.client;
+        let rows = match sqlquery(&sql).bind::<&String>(&pattern).fetch_all(c).await {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        let mut out = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            out.push(self.profile_from_row(&row).await?);
+        }
+
+        // return
+        Ok(out)
+    }
+
+    async fn get_profiles_with_skill(&self, skill: SkillName) -> Result<Vec<Profile>> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_profiles\" WHERE \"id\" IN (SELECT \"profile\" FROM \"sr_skills\" WHERE \"name\" = ?) ORDER BY \"username\""
+        } else {
+            "SELECT * FROM \"sr_profiles\" WHERE \"id\" IN (SELECT \"profile\" FROM \"sr_skills\" WHERE \"name\" = $1) ORDER BY \"username\""
+        };
+
+        let (_, name) = Self::skill_columns(&skill.into());
+
+        let c = &self.base.db.client;
+        let rows = match sqlquery(query).bind::<&String>(&name).fetch_all(c).await {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        let mut out = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            out.push(self.profile_from_row(&row).await?);
+        }
+
+        // return
+        Ok(out)
+    }
+
+    async fn get_profiles_with_expired_skills(&self, now: u128) -> Result<Vec<Profile>> {
+        // expiry times are stored as text, so they're compared here
+        let c = &self.base.db.client;
+        let rows = match sqlquery(
+            "SELECT \"profile\", \"expires_at\" FROM \"sr_skills\" WHERE \"expires_at\" <> ''",
+        )
+        .fetch_all(c)
+        .await
+        {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        let mut ids: Vec<String> = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            let expired = row
+                .get("expires_at")
+                .and_then(|e| e.parse::<u128>().ok())
+                .is_some_and(|e| e <= now);
+
+            match row.get("profile") {
+                Some(id) if expired && !ids.contains(id) => ids.push(id.to_string()),
+                _ => continue,
+            }
+        }
+
+        let mut out = Vec::new();
+
+        for id in ids {
+            if let Ok(p) = self.get_profile_by_hashed(id).await {
+                out.push(p);
+            }
+        }
+
+        // return
+        Ok(out)
+    }
+
+    // SET
+    async fn create_profile(&self, username: String, actor: &Actor) -> Result<String> {
+        // make sure user doesn't already exists
+        if let Ok(_) = &self.get_profile_by_username(username.clone()).await {
+            return Err(StrawError::MustBeUnique);
+        };
+
+        // check username
+        store::check_username(&username)?;
+
+        // ...
+        let (insert, insert_skill): (&str, &str) = if (self.base.db._type == "sqlite")
+            | (self.base.db._type == "mysql")
+        {
+            (
+                    "INSERT INTO \"sr_profiles\" (\"id\", \"uid\", \"username\", \"metadata\", \"joined\") VALUES (?, ?, ?, ?, ?)",
+                    "INSERT INTO \"sr_skills\" (\"profile\", \"position\", \"skill_type\", \"name\", \"value\", \"granted_at\", \"granted_by\") VALUES (?, ?, ?, ?, ?, ?, ?)",
+                )
+        } else {
+            (
+                    "INSERT INTO \"sr_profiles\" (\"id\", \"uid\", \"username\", \"metadata\", \"joined\") VALUES ($1, $2, $3, $4, $5)",
+                    "INSERT INTO \"sr_skills\" (\"profile\", \"position\", \"skill_type\", \"name\", \"value\", \"granted_at\", \"granted_by\") VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                )
+        };
+
+        let user_id_unhashed: String = dorsal::utility::uuid();
+        let user_id_hashed: String = dorsal::utility::hash(user_id_unhashed.clone());
+        let timestamp = utility::unix_epoch_timestamp().to_string();
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // the unique index on username makes sure only one concurrent request wins
+        if let Err(e) = sqlquery(insert)
+            .bind::<&String>(&user_id_hashed)
+            .bind::<&String>(&utility::uuid())
+            .bind::<&String>(&username.to_lowercase())
+            .bind::<&String>(
+                &serde_json::to_string::<ProfileMetadata>(&ProfileMetadata::default()).unwrap(),
+            )
+            .bind::<&String>(&timestamp)
+            .execute(&mut *tx)
+            .await
+        {
+            if e.as_database_error()
+                .is_some_and(|e| e.is_unique_violation())
+            {
+                return Err(StrawError::MustBeUnique);
+            }
+
+            return Err(StrawError::Other);
+        }
+
+        // every profile starts out as "Normal"
+        let skill: Skill = SkillName::Normal.into();
+        let (skill_type, name) = Self::skill_columns(&skill);
+
+        if sqlquery(insert_skill)
+            .bind::<&String>(&user_id_hashed)
+            .bind::<&str>("0")
+            .bind::<&String>(&skill_type)
+            .bind::<&String>(&name)
+            .bind::<&String>(&skill.1.to_string())
+            .bind::<&String>(&timestamp)
+            .bind::<&str>("")
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            actor.entry(&username.to_lowercase(), AuditAction::Register)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok(user_id_unhashed)
+    }
+
+    async fn edit_profile_metadata_by_name(
+        &self,
+        name: String,
+        metadata: ProfileMetadata,
+        version: u64,
+        actor: &Actor,
+    ) -> Result<u64> {
+        // make sure user exists
+        let profile = self.get_profile_by_username(name.clone()).await?;
+
+        // update user
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "UPDATE \"sr_profiles\" SET \"metadata\" = ?, \"version\" = ? WHERE \"id\" = ? AND \"version\" = ?"
+        } else {
+            "UPDATE \"sr_profiles\" SET (\"metadata\", \"version\") = ($1, $2) WHERE \"id\" = $3 AND \"version\" = $4"
+        };
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        let meta = &serde_json::to_string(&metadata).unwrap();
+        let updated = match sqlquery(query)
+            .bind::<&String>(meta)
+            .bind::<&String>(&(version + 1).to_string())
+            .bind::<&String>(&profile.id)
+            .bind::<&String>(&version.to_string())
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        if !updated {
+            // drop any stale cached copy, so the caller can retry
+            self.base
+                .cachedb
+                .remove(format!("sr_profile:{}", profile.username))
+                .await;
+
+            return Err(StrawError::Conflict);
+        }
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            actor.entry(&profile.username, AuditAction::Metadata)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        // only after the commit, so nothing can cache the old profile again
+        self.base
+            .cachedb
+            .remove(format!("sr_profile:{}", profile.username))
+            .await;
+
+        Ok(version + 1)
+    }
+
+    async fn get_secondary_tokens(&self, profile: String) -> Result<Vec<SecondaryToken>> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_secondary_tokens\" WHERE \"profile\" = ?"
+        } else {
+            "SELECT * FROM \"sr_secondary_tokens\" WHERE \"profile\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let rows = match sqlquery(query).bind::<&String>(&profile).fetch_all(c).await {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        let mut out: Vec<SecondaryToken> = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            out.push(SecondaryToken {
+                name: row.get("name").unwrap().to_string(),
+                token: row.get("token").unwrap().to_string(),
+                created: row.get("created").unwrap().parse::<u128>().unwrap_or(0),
+            });
+        }
+
+        out.sort_by_key(|t| t.created);
+        Ok(out)
+    }
+
+    async fn create_secondary_token(
+        &self,
+        name: String,
+        label: String,
+        actor: &Actor,
+    ) -> Result<String> {
+        let profile = self.get_profile_by_username(name.clone()).await?;
+
+        // check label
+        if label.is_empty() | (label.len() > 64) {
+            return Err(StrawError::ValueError);
+        }
+
+        if self
+            .get_secondary_tokens(profile.id.clone())
+            .await?
+            .iter()
+            .any(|t| t.name == label)
+        {
+            return Err(StrawError::MustBeUnique);
+        }
+
+        // add token
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "INSERT INTO \"sr_secondary_tokens\" VALUES (?, ?, ?, ?)"
+        } else {
+            "INSERT INTO \"sr_secondary_tokens\" VALUES ($1, $2, $3, $4)"
+        };
+
+        let token_unhashed: String = utility::random_id();
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        if let Err(e) = sqlquery(query)
+            .bind::<&String>(&utility::hash(token_unhashed.clone()))
+            .bind::<&String>(&profile.id)
+            .bind::<&String>(&label)
+            .bind::<&String>(&utility::unix_epoch_timestamp().to_string())
+            .execute(&mut *tx)
+            .await
+        {
+            if e.as_database_error()
+                .is_some_and(|e| e.is_unique_violation())
+            {
+                return Err(StrawError::MustBeUnique);
+            }
+
+            return Err(StrawError::Other);
+        }
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            actor.entry(&profile.username, AuditAction::CreateToken)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok(token_unhashed)
+    }
+
+    async fn delete_secondary_token(
+        &self,
+        name: String,
+        label: String,
+        actor: &Actor,
+    ) -> Result<()> {
+        let profile = self.get_profile_by_username(name.clone()).await?;
+
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "DELETE FROM \"sr_secondary_tokens\" WHERE \"profile\" = ? AND \"name\" = ?"
+        } else {
+            "DELETE FROM \"sr_secondary_tokens\" WHERE \"profile\" = $1 AND \"name\" = $2"
+        };
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        match sqlquery(query)
+            .bind::<&String>(&profile.id)
+            .bind::<&String>(&label)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(StrawError::NotFound);
+                }
+            }
+            Err(_) => return Err(StrawError::Other),
+        }
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            actor.entry(&profile.username, AuditAction::RevokeToken)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok(())
+    }
+
+    async fn edit_profile_skills_by_name(
+        &self,
+        name: String,
+        skills: SkillSet,
+        grants: Vec<SkillGrant>,
+        version: u64,
+        actor: &Actor,
+        action: AuditAction,
+    ) -> Result<u64> {
+        // make sure user exists
+        let profile = self.get_profile_by_username(name.clone()).await?;
+
+        // ...
+        let (bump, select, delete, insert): (&str, &str, &str, &str) = if (self.base.db._type
+            == "sqlite")
+            | (self.base.db._type == "mysql")
+        {
+            (
+                "UPDATE \"sr_profiles\" SET \"version\" = ? WHERE \"id\" = ? AND \"version\" = ?",
+                "SELECT * FROM \"sr_skills\" WHERE \"profile\" = ?",
+                "DELETE FROM \"sr_skills\" WHERE \"profile\" = ?",
+                "INSERT INTO \"sr_skills\" VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+        } else {
+            (
+                    "UPDATE \"sr_profiles\" SET \"version\" = $1 WHERE \"id\" = $2 AND \"version\" = $3",
+                    "SELECT * FROM \"sr_skills\" WHERE \"profile\" = $1",
+                    "DELETE FROM \"sr_skills\" WHERE \"profile\" = $1",
+                    "INSERT INTO \"sr_skills\" VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                )
+        };
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // claim the version first, this locks the profile until we commit
+        match sqlquery(bump)
+            .bind::<&String>(&(version + 1).to_string())
+            .bind::<&String>(&profile.id)
+            .bind::<&String>(&version.to_string())
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    // drop any stale cached copy, so the caller can retry
+                    self.base
+                        .cachedb
+                        .remove(format!("sr_profile:{}", profile.username))
+                        .await;
+
+                    return Err(StrawError::Conflict);
+                }
+            }
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // skills without a grant keep when (and by who) they were granted
+        let mut granted: HashMap<String, (String, String)> = HashMap::new();
+        let mut before: Vec<(i64, Skill)> = Vec::new();
+
+        match sqlquery(select)
+            .bind::<&String>(&profile.id)
+            .fetch_all(&mut *tx)
+            .await
+        {
+            Ok(rows) => {
+                for row in rows {
+                    let mut row = self.base.textify_row(row).data;
+                    before.push((
+                        row.get("position")
+                            .and_then(|p| p.parse::<i64>().ok())
+                            .unwrap_or(0),
+                        Self::skill_from_row(&row)?,
+                    ));
+
+                    granted.insert(
+                        row.remove("name").unwrap_or_default(),
+                        (
+                            row.remove("granted_at").unwrap_or_default(),
+                            row.remove("granted_by").unwrap_or_default(),
+                        ),
+                    );
+                }
+            }
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        if sqlquery(delete)
+            .bind::<&String>(&profile.id)
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        let timestamp = utility::unix_epoch_timestamp().to_string();
+
+        for (i, skill) in skills.iter().enumerate() {
+            let (skill_type, skill_name) = Self::skill_columns(skill);
+            let grant = grants.iter().find(|g| g.name == skill.0 .1);
+
+            let (granted_at, granted_by) = match grant {
+                Some(g) => (g.granted_at.to_string(), g.granted_by.clone()),
+                None => granted
+                    .get(&skill_name)
+                    .cloned()
+                    .unwrap_or((timestamp.clone(), String::new())),
+            };
+
+            let reason = grant.and_then(|g| g.reason.clone()).unwrap_or_default();
+            let expires_at = grant
+                .and_then(|g| g.expires_at)
+                .map(|e| e.to_string())
+                .unwrap_or_default();
+
+            if sqlquery(insert)
+                .bind::<&String>(&profile.id)
+                .bind::<&String>(&i.to_string())
+                .bind::<&String>(&skill_type)
+                .bind::<&String>(&skill_name)
+                .bind::<&String>(&skill.1.to_string())
+                .bind::<&String>(&granted_at)
+                .bind::<&String>(&granted_by)
+                .bind::<&String>(&expires_at)
+                .bind::<&String>(&reason)
+                .execute(&mut *tx)
+                .await
+                .is_err()
+            {
+                return Err(StrawError::Other);
+            }
+        }
+
+        before.sort_by_key(|(position, _)| *position);
+        let before: SkillSet = before.into_iter().map(|(_, s)| s).collect();
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            actor
+                .entry(&profile.username, action)
+                .skills(&before, &skills)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        self.base
+            .cachedb
+            .remove(format!("sr_profile:{}", profile.username))
+            .await;
+
+        Ok(version + 1)
+    }
+
+    async fn rotate_profile_key(&self, name: String, actor: &Actor) -> Result<String> {
+        let profile = self.get_profile_by_username(name.clone()).await?;
+
+        // ...
+        let updates: Vec<String> = [
+            ("sr_profiles", "id"),
+            ("sr_api_keys", "profile"),
+            ("sr_skills", "profile"),
+            ("sr_skills", "granted_by"),
+            ("sr_secondary_tokens", "profile"),
+            ("sr_handoffs", "profile"),
+            ("sr_oauth_clients", "owner"),
+            ("sr_oauth_codes", "profile"),
+            ("sr_oauth_tokens", "profile"),
+        ]
+        .iter()
+        .map(|(table, column)| {
+            if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                format!("UPDATE \"{table}\" SET \"{column}\" = ? WHERE \"{column}\" = ?")
+            } else {
+                format!("UPDATE \"{table}\" SET \"{column}\" = $1 WHERE \"{column}\" = $2")
+            }
+        })
+        .collect();
+
+        let (delete, granted): (&str, &str) = if (self.base.db._type == "sqlite")
+            | (self.base.db._type == "mysql")
+        {
+            (
+                "DELETE FROM \"sr_sessions\" WHERE \"profile\" = ?",
+                "SELECT DISTINCT \"username\" FROM \"sr_profiles\" WHERE \"id\" IN (SELECT \"profile\" FROM \"sr_skills\" WHERE \"granted_by\" = ?)",
+            )
+        } else {
+            (
+                "DELETE FROM \"sr_sessions\" WHERE \"profile\" = $1",
+                "SELECT DISTINCT \"username\" FROM \"sr_profiles\" WHERE \"id\" IN (SELECT \"profile\" FROM \"sr_skills\" WHERE \"granted_by\" = $1)",
+            )
+        };
+
+        let user_id_unhashed: String = utility::uuid();
+        let user_id_hashed: String = utility::hash(user_id_unhashed.clone());
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // every profile we granted a skill to has its cached grants changed too
+        let mut changed: Vec<String> = match sqlquery(granted)
+            .bind::<&String>(&profile.id)
+            .fetch_all(&mut *tx)
+            .await
+        {
+            Ok(rows) => rows
+                .into_iter()
+                .filter_map(|row| self.base.textify_row(row).data.remove("username"))
+                .collect(),
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        for query in &updates {
+            if sqlquery(query)
+                .bind::<&String>(&user_id_hashed)
+                .bind::<&String>(&profile.id)
+                .execute(&mut *tx)
+                .await
+                .is_err()
+            {
+                return Err(StrawError::Other);
+            }
+        }
+
+        if sqlquery(delete)
+            .bind::<&String>(&profile.id)
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            actor.entry(&profile.username, AuditAction::RotateKey)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        changed.push(profile.username.clone());
+        for username in changed {
+            self.base
+                .cachedb
+                .remove(format!("sr_profile:{username}"))
+                .await;
+        }
+
+        // return
+        Ok(user_id_unhashed)
+    }
+
+    async fn delete_profile_by_name(&self, name: String, actor: &Actor) -> Result<()> {
+        let profile = self.get_profile_by_username(name.clone()).await?;
+
+        // ...
+        let tables = [
+            ("sr_sessions", "profile"),
+            ("sr_api_keys", "profile"),
+            ("sr_handoffs", "profile"),
+            ("sr_oauth_codes", "profile"),
+            ("sr_oauth_tokens", "profile"),
+            ("sr_oauth_clients", "owner"),
+            ("sr_skills", "profile"),
+            ("sr_secondary_tokens", "profile"),
+            ("sr_profiles", "id"),
+        ];
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        for (table, column) in tables {
+            let query: String =
+                if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                    format!("DELETE FROM \"{table}\" WHERE \"{column}\" = ?")
+                } else {
+                    format!("DELETE FROM \"{table}\" WHERE \"{column}\" = $1")
+                };
+
+            if sqlquery(&query)
+                .bind::<&String>(&profile.id)
+                .execute(&mut *tx)
+                .await
+                .is_err()
+            {
+                return Err(StrawError::Other);
+            }
+        }
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            actor.entry(&profile.username, AuditAction::Delete)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        self.base
+            .cachedb
+            .remove(format!("sr_profile:{}", profile.username))
+            .await;
+
+        Ok(())
+    }
+
+    // sessions
+    // GET
+    async fn get_session_by_unhashed(&self, unhashed: String) -> Result<Session> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_sessions\" WHERE \"token\" = ?"
+        } else {
+            "SELECT * FROM \"sr_sessions\" WHERE \"token\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query)
+            .bind::<&String>(&utility::hash(unhashed))
+            .fetch_one(c)
+            .await
+        {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::NotFound),
+        };
+
+        // return
+        Ok(Session {
+            id: row.get("id").unwrap().to_string(),
+            token: row.get("token").unwrap().to_string(),
+            profile: row.get("profile").unwrap().to_string(),
+            created: row.get("created").unwrap().parse::<u128>().unwrap(),
+            last_used: row.get("last_used").unwrap().parse::<u128>().unwrap(),
+            user_agent: row.get("user_agent").unwrap().to_string(),
+            ip: row.get("ip").unwrap().to_string(),
+        })
+    }
+
+    async fn get_sessions_by_profile(&self, profile: String) -> Result<Vec<Session>> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_sessions\" WHERE \"profile\" = ? ORDER BY \"last_used\" DESC"
+        } else {
+            "SELECT * FROM \"sr_sessions\" WHERE \"profile\" = $1 ORDER BY \"last_used\" DESC"
+        };
+
+        let c = &self.base.db.client;
+        let rows = match sqlquery(query).bind::<&String>(&profile).fetch_all(c).await {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // ...
+        let mut output: Vec<Session> = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            output.push(Session {
+                id: row.get("id").unwrap().to_string(),
+                token: row.get("token").unwrap().to_string(),
+                profile: row.get("profile").unwrap().to_string(),
+                created: row.get("created").unwrap().parse::<u128>().unwrap(),
+                last_used: row.get("last_used").unwrap().parse::<u128>().unwrap(),
+                user_agent: row.get("user_agent").unwrap().to_string(),
+                ip: row.get("ip").unwrap().to_string(),
+            });
+        }
+
+        // return
+        Ok(output)
+    }
+
+    async fn get_profile_by_session(&self, unhashed: String) -> Result<(Profile, Session)> {
+        let session = self.get_session_by_unhashed(unhashed).await?;
+        let profile = self.get_profile_by_hashed(session.profile.clone()).await?;
+
+        // update last used
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "UPDATE \"sr_sessions\" SET \"last_used\" = ? WHERE \"id\" = ?"
+        } else {
+            "UPDATE \"sr_sessions\" SET \"last_used\" = $1 WHERE \"id\" = $2"
+        };
+
+        let c = &self.base.db.client;
+        let _ = sqlquery(query)
+            .bind::<&String>(&utility::unix_epoch_timestamp().to_string())
+            .bind::<&String>(&session.id)
+            .execute(c)
+            .await;
+
+        // return
+        Ok((profile, session))
+    }
+
+    // SET
+    async fn create_session(
+        &self,
+        profile: String,
+        user_agent: String,
+        ip: String,
+    ) -> Result<String> {
+        let username = self.get_profile_by_hashed(profile.clone()).await?.username;
+
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "INSERT INTO \"sr_sessions\" VALUES (?, ?, ?, ?, ?, ?, ?)"
+        } else {
+            "INSERT INTO \"sr_sessions\" VALUES ($1, $2, $3, $4, $5, $6, $7)"
+        };
+
+        let token_unhashed: String = utility::random_id();
+        let timestamp = utility::unix_epoch_timestamp().to_string();
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        if sqlquery(query)
+            .bind::<&String>(&utility::uuid())
+            .bind::<&String>(&utility::hash(token_unhashed.clone()))
+            .bind::<&String>(&profile)
+            .bind::<&String>(&timestamp)
+            .bind::<&String>(&timestamp)
+            .bind::<&String>(&user_agent)
+            .bind::<&String>(&ip)
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            Actor::new(&username)
+                .ip(&ip)
+                .entry(&username, AuditAction::Login)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok(token_unhashed)
+    }
+
+    async fn delete_session(&self, profile: String, id: String, actor: &Actor) -> Result<()> {
+        let username = self.get_profile_by_hashed(profile.clone()).await?.username;
+
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "DELETE FROM \"sr_sessions\" WHERE \"profile\" = ? AND \"id\" = ?"
+        } else {
+            "DELETE FROM \"sr_sessions\" WHERE \"profile\" = $1 AND \"id\" = $2"
+        };
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        match sqlquery(query)
+            .bind::<&String>(&profile)
+            .bind::<&String>(&id)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(StrawError::NotFound);
+                }
+            }
+            Err(_) => return Err(StrawError::Other),
+        }
+
+        // record the change along with it
+        audit!(self, tx, actor.entry(&username, AuditAction::Logout));
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_sessions_by_profile(&self, profile: String, actor: &Actor) -> Result<()> {
+        let username = self.get_profile_by_hashed(profile.clone()).await?.username;
+
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "DELETE FROM \"sr_sessions\" WHERE \"profile\" = ?"
+        } else {
+            "DELETE FROM \"sr_sessions\" WHERE \"profile\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        if sqlquery(query)
+            .bind::<&String>(&profile)
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        // record the change along with it
+        audit!(self, tx, actor.entry(&username, AuditAction::Logout));
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok(())
+    }
+
+    // api keys
+    // GET
+    async fn get_api_key_by_unhashed(&self, unhashed: String) -> Result<ApiKey> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_api_keys\" WHERE \"token\" = ?"
+        } else {
+            "SELECT * FROM \"sr_api_keys\" WHERE \"token\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query)
+            .bind::<&String>(&utility::hash(unhashed))
+            .fetch_one(c)
+            .await
+        {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::NotFound),
+        };
+
+        let key = ApiKey {
+            id: row.get("id").unwrap().to_string(),
+            token: row.get("token").unwrap().to_string(),
+            profile: row.get("profile").unwrap().to_string(),
+            label: row.get("label").unwrap().to_string(),
+            scope: match serde_json::from_str(row.get("scope").unwrap()) {
+                Ok(m) => m,
+                Err(_) => return Err(StrawError::ValueError),
+            },
+            created: row.get("created").unwrap().parse::<u128>().unwrap(),
+            expires: row.get("expires").unwrap().parse::<u128>().unwrap(),
+        };
+
+        // check expiry
+        if (key.expires != 0) && (utility::unix_epoch_timestamp() > key.expires) {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(key)
+    }
+
+    async fn get_api_keys_by_profile(&self, profile: String) -> Result<Vec<ApiKey>> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_api_keys\" WHERE \"profile\" = ?"
+        } else {
+            "SELECT * FROM \"sr_api_keys\" WHERE \"profile\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let rows = match sqlquery(query).bind::<&String>(&profile).fetch_all(c).await {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // ...
+        let mut output: Vec<ApiKey> = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            output.push(ApiKey {
+                id: row.get("id").unwrap().to_string(),
+                token: row.get("token").unwrap().to_string(),
+                profile: row.get("profile").unwrap().to_string(),
+                label: row.get("label").unwrap().to_string(),
+                scope: match serde_json::from_str(row.get("scope").unwrap()) {
+                    Ok(m) => m,
+                    Err(_) => return Err(StrawError::ValueError),
+                },
+                created: row.get("created").unwrap().parse::<u128>().unwrap(),
+                expires: row.get("expires").unwrap().parse::<u128>().unwrap(),
+            });
+        }
+
+        // return
+        Ok(output)
+    }
+
+    // SET
+    async fn create_api_key(
+        &self,
+        profile: String,
+        label: String,
+        scope: ApiKeyScope,
+        expires_in: Option<u128>,
+        actor: &Actor,
+    ) -> Result<(ApiKey, String)> {
+        let username = self.get_profile_by_hashed(profile.clone()).await?.username;
+
+        // check values
+        if label.is_empty() | (label.len() > 64) {
+            return Err(StrawError::ValueError);
+        }
+
+        if scope.max_power.map(|p| p < 0.0).unwrap_or(false) {
+            return Err(StrawError::ValueError);
+        }
+
+        // ...
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "INSERT INTO \"sr_api_keys\" VALUES (?, ?, ?, ?, ?, ?, ?)"
+        } else {
+            "INSERT INTO \"sr_api_keys\" VALUES ($1, $2, $3, $4, $5, $6, $7)"
+        };
+
+        let token_unhashed: String = utility::random_id();
+        let timestamp = utility::unix_epoch_timestamp();
+
+        let key = ApiKey {
+            id: utility::uuid(),
+            token: utility::hash(token_unhashed.clone()),
+            profile,
+            label,
+            scope,
+            created: timestamp,
+            expires: match expires_in {
+                Some(e) => timestamp + e,
+                None => 0,
+            },
+        };
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        if sqlquery(query)
+            .bind::<&String>(&key.id)
+            .bind::<&String>(&key.token)
+            .bind::<&String>(&key.profile)
+            .bind::<&String>(&key.label)
+            .bind::<&String>(&serde_json::to_string(&key.scope).unwrap())
+            .bind::<&String>(&key.created.to_string())
+            .bind::<&String>(&key.expires.to_string())
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        // record the change along with it
+        audit!(self, tx, actor.entry(&username, AuditAction::CreateToken));
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok((key, token_unhashed))
+    }
+
+    async fn delete_api_key(&self, profile: String, id: String, actor: &Actor) -> Result<()> {
+        let username = self.get_profile_by_hashed(profile.clone()).await?.username;
+
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "DELETE FROM \"sr_api_keys\" WHERE \"profile\" = ? AND \"id\" = ?"
+        } else {
+            "DELETE FROM \"sr_api_keys\" WHERE \"profile\" = $1 AND \"id\" = $2"
+        };
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        match sqlquery(query)
+            .bind::<&String>(&profile)
+            .bind::<&String>(&id)
+            .execute(&mut *tx)
+            .await
         {
-            Ok(_) => {
-                self.base
-                    .cachedb
-                    .remove(format!("sr_profile:{}", name))
-                    .await;
-                Ok(())
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(StrawError::NotFound);
+                }
             }
+            Err(_) => return Err(StrawError::Other),
+        }
+
+        // record the change along with it
+        audit!(self, tx, actor.entry(&username, AuditAction::RevokeToken));
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok(())
+    }
+
+    // handoffs
+    // SET
+    async fn create_handoff_code(&self, profile: String) -> Result<String> {
+        let (insert, clean): (&str, &str) =
+            if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                (
+                    "INSERT INTO \"sr_handoffs\" VALUES (?, ?, ?)",
+                    "DELETE FROM \"sr_handoffs\" WHERE CAST(\"created\" AS DECIMAL) < ?",
+                )
+            } else {
+                (
+                    "INSERT INTO \"sr_handoffs\" VALUES ($1, $2, $3)",
+                    "DELETE FROM \"sr_handoffs\" WHERE CAST(\"created\" AS DECIMAL) < $1",
+                )
+            };
+
+        let code_unhashed: String = utility::random_id();
+        let timestamp = utility::unix_epoch_timestamp();
+
+        let c = &self.base.db.client;
+
+        // remove expired codes
+        let _ = sqlquery(clean)
+            .bind::<i64>(timestamp.saturating_sub(HANDOFF_CODE_LIFETIME) as i64)
+            .execute(c)
+            .await;
+
+        // ...
+        match sqlquery(insert)
+            .bind::<&String>(&utility::hash(code_unhashed.clone()))
+            .bind::<&String>(&profile)
+            .bind::<&String>(&timestamp.to_string())
+            .execute(c)
+            .await
+        {
+            Ok(_) => Ok(code_unhashed),
             Err(_) => Err(StrawError::Other),
         }
     }
 
-    /// Update a [`Profile`]'s skills by its `username`
-    pub async fn edit_profile_skills_by_name(&self, name: String, skills: SkillSet) -> Result<()> {
-        // make sure user exists
-        if let Err(e) = self.get_profile_by_username(name.clone()).await {
-            return Err(e);
+    async fn consume_handoff_code(&self, unhashed: String) -> Result<String> {
+        let (select, delete): (&str, &str) =
+            if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                (
+                    "SELECT * FROM \"sr_handoffs\" WHERE \"code\" = ?",
+                    "DELETE FROM \"sr_handoffs\" WHERE \"code\" = ?",
+                )
+            } else {
+                (
+                    "SELECT * FROM \"sr_handoffs\" WHERE \"code\" = $1",
+                    "DELETE FROM \"sr_handoffs\" WHERE \"code\" = $1",
+                )
+            };
+
+        let hashed = utility::hash(unhashed);
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(select).bind::<&String>(&hashed).fetch_one(c).await {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::NotFound),
         };
 
-        // update user
+        // only the request which deletes the code may use it
+        match sqlquery(delete).bind::<&String>(&hashed).execute(c).await {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(StrawError::NotFound);
+                }
+            }
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // check expiry
+        let created = row.get("created").unwrap().parse::<u128>().unwrap();
+
+        if utility::unix_epoch_timestamp().saturating_sub(created) > HANDOFF_CODE_LIFETIME {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(row.get("profile").unwrap().to_string())
+    }
+
+    // oauth
+    // GET
+    async fn get_oauth_client(&self, id: String) -> Result<OAuthClient> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_oauth_clients\" WHERE \"id\" = ?"
+        } else {
+            "SELECT * FROM \"sr_oauth_clients\" WHERE \"id\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query).bind::<&String>(&id).fetch_one(c).await {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::NotFound),
+        };
+
+        // return
+        Ok(OAuthClient {
+            id: row.get("id").unwrap().to_string(),
+            secret: row.get("secret").unwrap().to_string(),
+            name: row.get("name").unwrap().to_string(),
+            redirect_uris: match serde_json::from_str(row.get("redirect_uris").unwrap()) {
+                Ok(m) => m,
+                Err(_) => return Err(StrawError::ValueError),
+            },
+            owner: row.get("owner").unwrap().to_string(),
+            created: row.get("created").unwrap().parse::<u128>().unwrap(),
+        })
+    }
+
+    async fn get_oauth_code(&self, unhashed: String) -> Result<OAuthCode> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_oauth_codes\" WHERE \"code\" = ?"
+        } else {
+            "SELECT * FROM \"sr_oauth_codes\" WHERE \"code\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query)
+            .bind::<&String>(&utility::hash(unhashed))
+            .fetch_one(c)
+            .await
+        {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::NotFound),
+        };
+
+        let code = Self::oauth_code_from_row(&row);
+
+        // check expiry
+        if utility::unix_epoch_timestamp().saturating_sub(code.created) > OAUTH_CODE_LIFETIME {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(code)
+    }
+
+    async fn get_oauth_token_by_unhashed(&self, unhashed: String) -> Result<OAuthToken> {
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "SELECT * FROM \"sr_oauth_tokens\" WHERE \"token\" = ?"
+        } else {
+            "SELECT * FROM \"sr_oauth_tokens\" WHERE \"token\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(query)
+            .bind::<&String>(&utility::hash(unhashed))
+            .fetch_one(c)
+            .await
+        {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::NotFound),
+        };
+
+        let token = OAuthToken {
+            token: row.get("token").unwrap().to_string(),
+            client: row.get("client").unwrap().to_string(),
+            profile: row.get("profile").unwrap().to_string(),
+            scope: row.get("scope").unwrap().to_string(),
+            created: row.get("created").unwrap().parse::<u128>().unwrap(),
+            expires: row.get("expires").unwrap().parse::<u128>().unwrap(),
+        };
+
+        // check expiry
+        if utility::unix_epoch_timestamp() > token.expires {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(token)
+    }
+
+    // SET
+    async fn create_oauth_client(
+        &self,
+        owner: String,
+        name: String,
+        redirect_uris: Vec<String>,
+        confidential: bool,
+        actor: &Actor,
+    ) -> Result<(OAuthClient, String)> {
+        // check values
+        store::check_oauth_client(&name, &redirect_uris)?;
+        let username = self.get_profile_by_hashed(owner.clone()).await?.username;
+
+        // ...
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "INSERT INTO \"sr_oauth_clients\" VALUES (?, ?, ?, ?, ?, ?)"
+        } else {
+            "INSERT INTO \"sr_oauth_clients\" VALUES ($1, $2, $3, $4, $5, $6)"
+        };
+
+        let secret_unhashed: String = if confidential {
+            utility::random_id()
+        } else {
+            String::new()
+        };
+
+        let client = OAuthClient {
+            id: utility::uuid(),
+            secret: if confidential {
+                utility::hash(secret_unhashed.clone())
+            } else {
+                String::new()
+            },
+            name,
+            redirect_uris,
+            owner,
+            created: utility::unix_epoch_timestamp(),
+        };
+
+        let c = &self.base.db.client;
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        if sqlquery(query)
+            .bind::<&String>(&client.id)
+            .bind::<&String>(&client.secret)
+            .bind::<&String>(&client.name)
+            .bind::<&String>(&serde_json::to_string(&client.redirect_uris).unwrap())
+            .bind::<&String>(&client.owner)
+            .bind::<&String>(&client.created.to_string())
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        // record the change along with it
+        audit!(self, tx, actor.entry(&username, AuditAction::CreateClient));
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok((client, secret_unhashed))
+    }
+
+    async fn create_oauth_code(&self, mut code: OAuthCode) -> Result<String> {
+        let (query, clean): (&str, &str) =
+            if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                (
+                    "INSERT INTO \"sr_oauth_codes\" VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                    "DELETE FROM \"sr_oauth_codes\" WHERE CAST(\"created\" AS DECIMAL) < ?",
+                )
+            } else {
+                (
+                    "INSERT INTO \"sr_oauth_codes\" VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                    "DELETE FROM \"sr_oauth_codes\" WHERE CAST(\"created\" AS DECIMAL) < $1",
+                )
+            };
+
+        let code_unhashed: String = utility::random_id();
+        code.code = utility::hash(code_unhashed.clone());
+        code.created = utility::unix_epoch_timestamp();
+
+        let c = &self.base.db.client;
+
+        // remove expired codes
+        let _ = sqlquery(clean)
+            .bind::<i64>((code.created - OAUTH_CODE_LIFETIME) as i64)
+            .execute(c)
+            .await;
+
+        // ...
+        match sqlquery(query)
+            .bind::<&String>(&code.code)
+            .bind::<&String>(&code.client)
+            .bind::<&String>(&code.profile)
+            .bind::<&String>(&code.redirect_uri)
+            .bind::<&String>(&code.scope)
+            .bind::<&String>(&code.challenge)
+            .bind::<&String>(&code.nonce)
+            .bind::<&String>(&code.created.to_string())
+            .execute(c)
+            .await
+        {
+            Ok(_) => Ok(code_unhashed),
+            Err(_) => Err(StrawError::Other),
+        }
+    }
+
+    async fn consume_oauth_code(&self, unhashed: String) -> Result<OAuthCode> {
+        let (select, delete): (&str, &str) =
+            if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                (
+                    "SELECT * FROM \"sr_oauth_codes\" WHERE \"code\" = ?",
+                    "DELETE FROM \"sr_oauth_codes\" WHERE \"code\" = ?",
+                )
+            } else {
+                (
+                    "SELECT * FROM \"sr_oauth_codes\" WHERE \"code\" = $1",
+                    "DELETE FROM \"sr_oauth_codes\" WHERE \"code\" = $1",
+                )
+            };
+
+        let hashed = utility::hash(unhashed);
+
+        let c = &self.base.db.client;
+        let row = match sqlquery(select).bind::<&String>(&hashed).fetch_one(c).await {
+            Ok(r) => self.base.textify_row(r).data,
+            Err(_) => return Err(StrawError::NotFound),
+        };
+
+        // only the request which deletes the code may use it
+        match sqlquery(delete).bind::<&String>(&hashed).execute(c).await {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(StrawError::NotFound);
+                }
+            }
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        let code = Self::oauth_code_from_row(&row);
+
+        // check expiry
+        if utility::unix_epoch_timestamp().saturating_sub(code.created) > OAUTH_CODE_LIFETIME {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(code)
+    }
+
+    async fn create_oauth_token(
+        &self,
+        client: String,
+        profile: String,
+        scope: String,
+    ) -> Result<(OAuthToken, String)> {
+        let username = self.get_profile_by_hashed(profile.clone()).await?.username;
+
+        let (query, clean): (&str, &str) =
+            if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                (
+                    "INSERT INTO \"sr_oauth_tokens\" VALUES (?, ?, ?, ?, ?, ?)",
+                    "DELETE FROM \"sr_oauth_tokens\" WHERE CAST(\"expires\" AS DECIMAL) < ?",
+                )
+            } else {
+                (
+                    "INSERT INTO \"sr_oauth_tokens\" VALUES ($1, $2, $3, $4, $5, $6)",
+                    "DELETE FROM \"sr_oauth_tokens\" WHERE CAST(\"expires\" AS DECIMAL) < $1",
+                )
+            };
+
+        let token_unhashed: String = utility::random_id();
+        let timestamp = utility::unix_epoch_timestamp();
+
+        let token = OAuthToken {
+            token: utility::hash(token_unhashed.clone()),
+            client,
+            profile,
+            scope,
+            created: timestamp,
+            expires: timestamp + OAUTH_TOKEN_LIFETIME,
+        };
+
+        let c = &self.base.db.client;
+
+        // remove expired tokens
+        let _ = sqlquery(clean)
+            .bind::<i64>(timestamp as i64)
+            .execute(c)
+            .await;
+
+        // ...
+        let mut tx = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        if sqlquery(query)
+            .bind::<&String>(&token.token)
+            .bind::<&String>(&token.client)
+            .bind::<&String>(&token.profile)
+            .bind::<&String>(&token.scope)
+            .bind::<&String>(&token.created.to_string())
+            .bind::<&String>(&token.expires.to_string())
+            .execute(&mut *tx)
+            .await
+            .is_err()
+        {
+            return Err(StrawError::Other);
+        }
+
+        // record the change along with it
+        audit!(
+            self,
+            tx,
+            Actor::new(&username).entry(&username, AuditAction::Login)
+        );
+
+        if tx.commit().await.is_err() {
+            return Err(StrawError::Other);
+        }
+
+        Ok((token, token_unhashed))
+    }
+
+    // keys
+    // GET
+    async fn get_signing_keys(&self) -> Result<Vec<SigningKey>> {
+        let c = &self.base.db.client;
+        let rows = match sqlquery("SELECT * FROM \"sr_keys\"").fetch_all(c).await {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        // ...
+        let mut output: Vec<SigningKey> = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            output.push(SigningKey {
+                id: row.get("id").unwrap().to_string(),
+                private: row.get("private").unwrap().to_string(),
+                public: row.get("public").unwrap().to_string(),
+                created: row.get("created").unwrap().parse::<u128>().unwrap(),
+            });
+        }
+
+        // return
+        output.sort_by_key(|k| std::cmp::Reverse(k.created));
+        Ok(output)
+    }
+
+    // SET
+    async fn create_signing_key(&self) -> Result<SigningKey> {
+        let key = store::generate_signing_key()?;
+
+        // ...
         let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
-            "UPDATE \"sr_profiles\" SET \"skills\" = ? WHERE \"username\" = ?"
+            "INSERT INTO \"sr_keys\" VALUES (?, ?, ?, ?)"
         } else {
-            "UPDATE \"sr_profiles\" SET (\"skills\") = ($1) WHERE \"username\" = $2"
+            "INSERT INTO \"sr_keys\" VALUES ($1, $2, $3, $4)"
         };
 
         let c = &self.base.db.client;
-        let skills = &serde_json::to_string(&skills).unwrap();
         match sqlquery(query)
-            .bind::<&String>(skills)
-            .bind::<&String>(&name)
+            .bind::<&String>(&key.id)
+            .bind::<&String>(&key.private)
+            .bind::<&String>(&key.public)
+            .bind::<&String>(&key.created.to_string())
             .execute(c)
             .await
         {
-            Ok(_) => {
-                self.base
-                    .cachedb
-                    .remove(format!("sr_profile:{}", name))
-                    .await;
-                Ok(())
+            Ok(_) => Ok(key),
+            Err(_) => Err(StrawError::Other),
+        }
+    }
+
+    async fn rotate_signing_keys(&self) -> Result<()> {
+        let keys = self.get_signing_keys().await?;
+        let now = utility::unix_epoch_timestamp();
+
+        // delete keys that have been replaced for longer than the token lifetime (a key
+        // signs tokens until the next key is created, however late that was)
+        let query: &str = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            "DELETE FROM \"sr_keys\" WHERE \"id\" = ?"
+        } else {
+            "DELETE FROM \"sr_keys\" WHERE \"id\" = $1"
+        };
+
+        let c = &self.base.db.client;
+        for pair in keys.windows(2) {
+            let (next, key) = (&pair[0], &pair[1]);
+
+            if now > next.created + self.config.jwt_lifetime {
+                let _ = sqlquery(query).bind::<&String>(&key.id).execute(c).await;
+            }
+        }
+
+        // create a new key if the current one has been used for long enough
+        if keys
+            .first()
+            .map(|k| now >= k.created + self.config.key_rotation_interval)
+            .unwrap_or(true)
+        {
+            self.create_signing_key().await?;
+        }
+
+        Ok(())
+    }
+
+    // audit
+    // GET
+    async fn get_audit_entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let action = query.action.as_ref().map(Self::audit_action_column);
+
+        // only the given filters are added, in order
+        let mut conditions: Vec<String> = Vec::new();
+        let mut values: Vec<&String> = Vec::new();
+
+        for (column, value) in [
+            ("actor", query.actor.as_ref()),
+            ("target", query.target.as_ref()),
+            ("action", action.as_ref()),
+        ] {
+            if let Some(value) = value {
+                values.push(value);
+                conditions.push(
+                    if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                        format!("\"{column}\" = ?")
+                    } else {
+                        format!("\"{column}\" = ${}", values.len())
+                    },
+                );
             }
+        }
+
+        // keyset paging, "created" is text so it's compared as a number (like the memory
+        // store does)
+        if query.before.is_some() {
+            conditions.push(
+                if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                    "(CAST(\"created\" AS DECIMAL) < ? OR (CAST(\"created\" AS DECIMAL) = ? AND \"id\" < ?))".to_string()
+                } else {
+                    let n = values.len();
+                    format!(
+                        "(CAST(\"created\" AS DECIMAL) < ${} OR (CAST(\"created\" AS DECIMAL) = ${} AND \"id\" < ${}))",
+                        n + 1,
+                        n + 2,
+                        n + 3
+                    )
+                },
+            );
+        }
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        };
+
+        let sql = format!(
+            "SELECT * FROM \"sr_audit\"{filter} ORDER BY CAST(\"created\" AS DECIMAL) DESC, \"id\" DESC LIMIT {} OFFSET {}",
+            query.limit.min(500),
+            query.offset
+        );
+
+        let mut q = sqlquery(&sql);
+        for value in values {
+            q = q.bind::<&String>(value);
+        }
+
+        if let Some((timestamp, id)) = query.before.as_ref() {
+            q = q
+                .bind::<i64>(*timestamp as i64)
+                .bind::<i64>(*timestamp as i64)
+                .bind::<&String>(id);
+        }
+
+        let c = &self.base.db.client;
+        let rows = match q.fetch_all(c).await {
+            Ok(r) => r,
+            Err(_) => return Err(StrawError::Other),
+        };
+
+        let mut out = Vec::new();
+
+        for row in rows {
+            let row = self.base.textify_row(row).data;
+            out.push(Self::audit_from_row(&row)?);
+        }
+
+        // return
+        Ok(out)
+    }
+
+    // SET
+    async fn create_audit_entry(&self, entry: AuditEntry) -> Result<()> {
+        let (query, values) = self.audit_statement(&entry);
+        let mut q = sqlquery(query);
+
+        for value in &values {
+            q = q.bind::<&String>(value);
+        }
+
+        let c = &self.base.db.client;
+        match q.execute(c).await {
+            Ok(_) => Ok(()),
             Err(_) => Err(StrawError::Other),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_allowlist() {
+        let options = ServerOptions {
+            redirect_allowlist: vec!["/app".to_string(), "https://example.com/".to_string()],
+            ..ServerOptions::truthy()
+        };
+
+        assert!(options.redirect_allowed("/app"));
+        assert!(options.redirect_allowed("/app/settings?tab=keys"));
+        assert!(options.redirect_allowed("https://example.com/callback"));
+
+        // prefixes must end at a path boundary
+        assert!(!options.redirect_allowed("/apple"));
+        assert!(!options.redirect_allowed("https://example.com.evil.com/"));
+
+        // nothing which leaves the origin through "/"
+        let options = ServerOptions::truthy();
+        assert!(options.redirect_allowed("/dashboard"));
+        assert!(!options.redirect_allowed("//evil.com"));
+        assert!(!options.redirect_allowed("/\\evil.com"));
+        assert!(!options.redirect_allowed("/\t/evil.com"));
+    }
+}
@@ -0,0 +1,276 @@
+//! Request extractors for host applications
+use crate::database::{Database, ServerOptions, TokenSource};
+use crate::model::{ApiKey, Profile, ProfileStats, Session, SkillManager, StrawError};
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use axum_extra::extract::cookie::CookieJar;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+/// The default name of the cookie which stores the profile token ([`crate::database::CookieOptions::name`])
+pub const TOKEN_COOKIE: &str = "__Secure-Token";
+/// The name of the header which can store the profile token
+pub const TOKEN_HEADER: &str = "X-Straw-Token";
+
+/// Get the token from an `Authorization: Bearer` header
+pub fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get("Authorization")?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+
+    if token.is_empty() {
+        return None;
+    }
+
+    Some(token.to_string())
+}
+
+/// Get the token from the first of the [`ServerOptions::token_sources`] which has one
+pub fn request_token(headers: &HeaderMap, options: &ServerOptions) -> Option<String> {
+    for source in &options.token_sources {
+        let token = match source {
+            TokenSource::Cookie => CookieJar::from_headers(headers)
+                .get(&options.cookie.name)
+                .map(|c| c.value_trimmed().to_string()),
+            TokenSource::Bearer => bearer_token(headers),
+            TokenSource::Header => headers
+                .get(TOKEN_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().to_string()),
+        };
+
+        if let Some(token) = token.filter(|t| !t.is_empty()) {
+            return Some(token);
+        }
+    }
+
+    None
+}
+
+/// An authenticated [`Profile`] resolved from the request token
+///
+/// If the token is an [`ApiKey`], the skills of the profile are restricted to the
+/// key's scope before its stats are computed.
+///
+/// The token is read from the sources in [`ServerOptions::token_sources`](crate::ServerOptions::token_sources).
+/// Rejects with [`StrawError::NotAllowed`] if the request is not authenticated.
+/// Works with any state that a [`Database`] can be pulled from through [`FromRef`].
+#[derive(Clone, Debug)]
+pub struct AuthProfile {
+    /// The authenticated profile
+    pub profile: Profile,
+    /// The stats of the authenticated profile ([`SkillManager::get_stats`])
+    pub stats: ProfileStats,
+    /// The session used to authenticate, if the token was a session token
+    pub session: Option<Session>,
+    /// The API key used to authenticate, if the token was an API key
+    pub api_key: Option<ApiKey>,
+}
+
+impl AuthProfile {
+    /// Get a [`SkillManager`] for the skills the request can act with
+    ///
+    /// Built from `stats`, so an [`ApiKey`] only carries the skills in its scope.
+    pub fn manager(&self) -> SkillManager {
+        SkillManager::with_grants(self.stats.skills.clone(), self.profile.grants.clone())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthProfile
+where
+    Database: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = StrawError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let database = Database::from_ref(state);
+
+        // get token
+        let token = match request_token(&parts.headers, &database.config) {
+            Some(t) => t,
+            None => return Err(StrawError::NotAllowed),
+        };
+
+        // get profile from token
+        let mut session = None;
+        let mut api_key = None;
+
+        let profile = if let Ok((p, s)) = database.get_profile_by_session(token.clone()).await {
+            session = Some(s);
+            p
+        } else if let Ok(k) = database.get_api_key_by_unhashed(token.clone()).await {
+            let mut p = match database.get_profile_by_hashed(k.profile.clone()).await {
+                Ok(p) => p,
+                Err(_) => return Err(StrawError::NotAllowed),
+            };
+
+            p.skills = k.scope.restrict(p.skills);
+            api_key = Some(k);
+            p
+        } else {
+            match database.get_profile_by_unhashed(token).await {
+                Ok(p) => p,
+                Err(_) => return Err(StrawError::NotAllowed),
+            }
+        };
+
+        // return
+        let stats = SkillManager::from(&profile).get_stats();
+        Ok(Self {
+            profile,
+            stats,
+            session,
+            api_key,
+        })
+    }
+}
+
+/// An [`AuthProfile`] which doesn't reject unauthenticated requests
+#[derive(Clone, Debug)]
+pub struct OptionalAuthProfile(pub Option<AuthProfile>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OptionalAuthProfile
+where
+    Database: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            AuthProfile::from_request_parts(parts, state).await.ok(),
+        ))
+    }
+}
+
+/// Information about the client which made a request
+///
+/// The IP address is taken from [`ConnectInfo`] if the server was started with it.
+/// If [`ServerOptions::trusted_proxy`] is set, the last address in `X-Forwarded-For`
+/// (the one the proxy added) or the `X-Real-IP` header is used instead.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub user_agent: String,
+    pub ip: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    Database: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let database = Database::from_ref(state);
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().to_string())
+        };
+
+        let user_agent = header("User-Agent").unwrap_or_default();
+        let forwarded = if database.config.trusted_proxy {
+            // the client can send any addresses it likes, only the last one was added by
+            // our proxy
+            parts
+                .headers
+                .get_all("X-Forwarded-For")
+                .iter()
+                .next_back()
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit(',').next())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .or_else(|| header("X-Real-IP"))
+        } else {
+            None
+        };
+
+        let ip = match forwarded {
+            Some(v) => v,
+            None => parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|c| c.0.ip().to_string())
+                .unwrap_or_default(),
+        };
+
+        Ok(Self { user_agent, ip })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerOptions;
+    use axum::http::Request;
+
+    async fn client_ip(trusted_proxy: bool, headers: &[(&str, &str)]) -> String {
+        let database = Database::memory(ServerOptions {
+            trusted_proxy,
+            ..ServerOptions::truthy()
+        });
+
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        ClientInfo::from_request_parts(&mut parts, &database)
+            .await
+            .unwrap()
+            .ip
+    }
+
+    #[tokio::test]
+    async fn forwarded_for_uses_the_proxy_entry() {
+        // the client sent "1.1.1.1", the proxy appended the address it saw
+        let headers = [("X-Forwarded-For", "1.1.1.1, 10.0.0.2,203.0.113.7")];
+        assert_eq!(client_ip(true, &headers).await, "203.0.113.7");
+
+        // with more than one header, the proxy's is the last
+        let headers = [
+            ("X-Forwarded-For", "1.1.1.1"),
+            ("X-Forwarded-For", "10.0.0.2, 203.0.113.7"),
+        ];
+        assert_eq!(client_ip(true, &headers).await, "203.0.113.7");
+
+        let headers = [("X-Real-IP", "203.0.113.7")];
+        assert_eq!(client_ip(true, &headers).await, "203.0.113.7");
+
+        // never trusted without a proxy
+        let headers = [("X-Forwarded-For", "1.1.1.1"), ("X-Real-IP", "1.1.1.1")];
+        assert_eq!(client_ip(false, &headers).await, "");
+    }
+
+    #[test]
+    fn token_sources_are_read_in_order() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", "Bearer bearer".parse().unwrap());
+        headers.insert(TOKEN_HEADER, "header".parse().unwrap());
+
+        let mut options = ServerOptions::truthy();
+        assert_eq!(request_token(&headers, &options).as_deref(), Some("bearer"));
+
+        options.token_sources = vec![TokenSource::Header, TokenSource::Bearer];
+        assert_eq!(request_token(&headers, &options).as_deref(), Some("header"));
+
+        // an empty token falls through to the next source
+        headers.insert(TOKEN_HEADER, " ".parse().unwrap());
+        assert_eq!(request_token(&headers, &options).as_deref(), Some("bearer"));
+
+        // sources which aren't enabled are never read
+        options.token_sources = vec![TokenSource::Cookie];
+        assert_eq!(request_token(&headers, &options), None);
+    }
+}
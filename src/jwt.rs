@@ -0,0 +1,165 @@
+//! Signed JWTs embedding profile stats
+use crate::database::{Database, Result};
+use crate::extract::AuthProfile;
+use crate::model::{Profile, SkillManager, SkillName, StrawError};
+use dorsal::{utility, DefaultReturn};
+
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+/// The claims of a JWT issued by starstraw
+///
+/// The profile's stats are embedded, but not its hashed ID ([`Profile::id`]): it
+/// changes when the key is rotated and is only ever needed to look up the account key.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Claims {
+    pub iss: String,
+    /// The public ID of the profile ([`Profile::uid`])
+    pub sub: String,
+    /// The OAuth client the token was issued to, if any
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub aud: String,
+    /// Issued at (seconds)
+    pub iat: u64,
+    /// Expires at (seconds)
+    pub exp: u64,
+    pub preferred_username: String,
+    pub title: SkillName,
+    pub power: f32,
+    pub defense: f32,
+    pub abilities: Vec<SkillName>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub nonce: String,
+}
+
+impl Claims {
+    /// Create claims for the given [`Profile`] using its current stats
+    pub fn new(database: &Database, profile: &Profile) -> Self {
+        let stats = SkillManager::from(profile).get_stats();
+        let now = (utility::unix_epoch_timestamp() / 1000) as u64;
+
+        Self {
+            iss: database.config.issuer.trim_end_matches('/').to_string(),
+            sub: profile.uid.clone(),
+            aud: String::new(),
+            iat: now,
+            exp: now + (database.config.jwt_lifetime / 1000) as u64,
+            preferred_username: profile.username.clone(),
+            title: stats.title,
+            power: stats.power,
+            defense: stats.defense,
+            abilities: stats.abilities.into_keys().collect(),
+            nonce: String::new(),
+        }
+    }
+}
+
+/// Sign the given [`Claims`] with the current signing key
+pub async fn sign(database: &Database, claims: &Claims) -> Result<String> {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    let mut keys = database.get_signing_keys().await?;
+
+    if keys.is_empty() {
+        keys.push(database.create_signing_key().await?);
+    }
+
+    let key = &keys[0];
+    let der = match STANDARD.decode(&key.private) {
+        Ok(d) => d,
+        Err(_) => return Err(StrawError::Other),
+    };
+
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(key.id.clone());
+
+    match jsonwebtoken::encode(&header, claims, &EncodingKey::from_ed_der(&der)) {
+        Ok(t) => Ok(t),
+        Err(_) => Err(StrawError::Other),
+    }
+}
+
+/// Verify a JWT signed by any published key, returning its [`Claims`]
+pub async fn verify(database: &Database, token: &str) -> Result<Claims> {
+    let header = match jsonwebtoken::decode_header(token) {
+        Ok(h) => h,
+        Err(_) => return Err(StrawError::ValueError),
+    };
+
+    // find key
+    let keys = database.get_signing_keys().await?;
+    let key = match keys.iter().find(|k| Some(&k.id) == header.kid.as_ref()) {
+        Some(k) => k,
+        None => return Err(StrawError::NotAllowed),
+    };
+
+    let key = match DecodingKey::from_ed_components(&key.public) {
+        Ok(k) => k,
+        Err(_) => return Err(StrawError::Other),
+    };
+
+    // validate
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.validate_aud = false;
+    validation.set_issuer(&[database.config.issuer.trim_end_matches('/')]);
+
+    match jsonwebtoken::decode::<Claims>(token, &key, &validation) {
+        Ok(t) => Ok(t.claims),
+        Err(_) => Err(StrawError::NotAllowed),
+    }
+}
+
+/// JSON Web Key Set of every published signing key
+pub async fn jwks_request(State(database): State<Database>) -> impl IntoResponse {
+    let keys = database.get_signing_keys().await.unwrap_or_default();
+
+    Json(serde_json::json!({
+        "keys": keys
+            .iter()
+            .map(|k| {
+                serde_json::json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "alg": "EdDSA",
+                    "use": "sig",
+                    "kid": k.id,
+                    "x": k.public,
+                })
+            })
+            .collect::<Vec<serde_json::Value>>(),
+    }))
+}
+
+/// Issue a JWT for the current profile
+///
+/// The token is returned as the message. API keys can't be exchanged for a JWT.
+pub async fn create_jwt_request(
+    auth: AuthProfile,
+    State(database): State<Database>,
+) -> impl IntoResponse {
+    // the claims name the profile's title, which a scoped api key may not carry
+    if auth.api_key.is_some() {
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
+            payload: (),
+        });
+    }
+
+    match sign(&database, &Claims::new(&database, &auth.profile)).await {
+        Ok(token) => Json(DefaultReturn {
+            success: true,
+            message: token,
+            payload: (),
+        }),
+        Err(e) => Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: (),
+        }),
+    }
+}
@@ -0,0 +1,139 @@
+//! Tower middleware for protecting host routes
+use crate::database::Database;
+use crate::extract::AuthProfile;
+use crate::model::{ProfileStats, SkillManager, SkillName, StrawError};
+
+use axum::extract::{FromRequestParts, Request};
+use axum::response::{IntoResponse, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// A rule which the [`ProfileStats`] of the caller must satisfy
+#[derive(Clone, Debug)]
+pub enum Requirement {
+    /// The profile must hold the given skill
+    Skill(SkillName),
+    /// The profile must hold the given title
+    Title(SkillName),
+    /// The profile must have at least the given power level
+    Power(f32),
+}
+
+impl Requirement {
+    /// Check if the given [`ProfileStats`] satisfy the requirement
+    pub fn check(&self, stats: &ProfileStats) -> bool {
+        match self {
+            Requirement::Skill(name) => {
+                SkillManager::new(stats.skills.clone()).has_skill(name.clone())
+            }
+            Requirement::Title(name) => &stats.title == name,
+            Requirement::Power(power) => stats.power >= *power,
+        }
+    }
+}
+
+/// [`Layer`] which rejects requests with [`StrawError::NotAllowed`] if the caller
+/// does not satisfy the given [`Requirement`]
+///
+/// The resolved [`AuthProfile`] is inserted into the request extensions, so it can
+/// be taken by inner handlers with `Extension<AuthProfile>`.
+///
+/// ```ignore
+/// let admin = Router::new()
+///     .route("/panel", get(panel))
+///     .layer(RequireLayer::title(database.clone(), SkillName::Administrator));
+/// ```
+#[derive(Clone)]
+pub struct RequireLayer {
+    database: Database,
+    requirement: Requirement,
+}
+
+impl RequireLayer {
+    /// Create a new [`RequireLayer`]
+    pub fn new(database: Database, requirement: Requirement) -> Self {
+        Self {
+            database,
+            requirement,
+        }
+    }
+
+    /// Require the caller to hold the given skill
+    pub fn skill(database: Database, skill: SkillName) -> Self {
+        Self::new(database, Requirement::Skill(skill))
+    }
+
+    /// Require the caller to hold the given title
+    pub fn title(database: Database, title: SkillName) -> Self {
+        Self::new(database, Requirement::Title(title))
+    }
+
+    /// Require the caller to have at least the given power level
+    pub fn power(database: Database, power: f32) -> Self {
+        Self::new(database, Requirement::Power(power))
+    }
+}
+
+impl<S> Layer<S> for RequireLayer {
+    type Service = RequireService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireService {
+            inner,
+            database: self.database.clone(),
+            requirement: self.requirement.clone(),
+        }
+    }
+}
+
+/// [`Service`] created by [`RequireLayer`]
+#[derive(Clone)]
+pub struct RequireService<S> {
+    inner: S,
+    database: Database,
+    requirement: Requirement,
+}
+
+impl<S> Service<Request> for RequireService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // take the service which was driven to readiness
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let database = self.database.clone();
+        let requirement = self.requirement.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+
+            // get user from token
+            let auth = match AuthProfile::from_request_parts(&mut parts, &database).await {
+                Ok(a) => a,
+                Err(e) => return Ok(e.into_response()),
+            };
+
+            // check requirement
+            if !requirement.check(&auth.stats) {
+                return Ok(StrawError::NotAllowed.into_response());
+            }
+
+            // continue
+            parts.extensions.insert(auth);
+            inner.call(Request::from_parts(parts, body)).await
+        })
+    }
+}
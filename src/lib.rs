@@ -1,7 +1,23 @@
 //! Starstraw Library
 pub mod api;
 pub mod database;
+pub mod extract;
+pub mod jwt;
+pub mod layer;
+pub mod memory;
+pub mod migration;
 pub mod model;
+pub mod oauth;
+pub mod registry;
+pub mod store;
 
-pub use database::{Database, ServerOptions};
+pub use database::{
+    CookieOptions, Database, RegistrationPolicy, ServerOptions, SqlStore, TokenSource,
+};
 pub use dorsal::DatabaseOpts;
+pub use extract::{AuthProfile, ClientInfo, OptionalAuthProfile};
+pub use layer::{RequireLayer, Requirement};
+pub use memory::MemoryStore;
+pub use migration::{Migration, MigrationError};
+pub use registry::{SkillDefinition, SkillRegistry, SkillRules};
+pub use store::ProfileStore;
@@ -0,0 +1,936 @@
+//! In-memory [`ProfileStore`]
+//!
+//! Nothing is persisted, so this store is meant for tests and ephemeral demo deployments:
+//!
+//! ```ignore
+//! let database = Database::memory(ServerOptions::truthy());
+//! database.init().await.unwrap();
+//! ```
+use crate::database::{
+    Result, ServerOptions, HANDOFF_CODE_LIFETIME, OAUTH_CODE_LIFETIME, OAUTH_TOKEN_LIFETIME,
+};
+use crate::migration::{Migration, MigrationError};
+use crate::model::{
+    Actor, ApiKey, ApiKeyScope, AuditAction, AuditEntry, AuditQuery, GrantContext, OAuthClient,
+    OAuthCode, OAuthToken, Profile, ProfileMetadata, SecondaryToken, Session, SigningKey, Skill,
+    SkillGrant, SkillName, SkillSet, SkillType, StrawError,
+};
+use crate::store::{self, ProfileStore};
+use dorsal::utility;
+use std::sync::{Mutex, MutexGuard};
+
+/// Everything held by a [`MemoryStore`]
+#[derive(Default)]
+struct State {
+    profiles: Vec<Profile>,
+    /// (profile, token)
+    secondary_tokens: Vec<(String, SecondaryToken)>,
+    sessions: Vec<Session>,
+    api_keys: Vec<ApiKey>,
+    /// (hashed code, profile, created)
+    handoffs: Vec<(String, String, u128)>,
+    oauth_clients: Vec<OAuthClient>,
+    oauth_codes: Vec<OAuthCode>,
+    oauth_tokens: Vec<OAuthToken>,
+    keys: Vec<SigningKey>,
+    /// Oldest first
+    audit: Vec<AuditEntry>,
+    /// If the owner has been granted their title ([`ProfileStore::bootstrap_owner`])
+    bootstrapped: bool,
+}
+
+/// [`ProfileStore`] which keeps everything in memory
+pub struct MemoryStore {
+    pub config: ServerOptions,
+    state: Mutex<State>,
+}
+
+impl MemoryStore {
+    /// Create a new, empty [`MemoryStore`]
+    pub fn new(server_options: ServerOptions) -> Self {
+        Self {
+            config: server_options,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // a panic while holding the lock can't leave the state half-written
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl State {
+    fn profile_by_username(&mut self, username: &str) -> Result<&mut Profile> {
+        let username = username.to_lowercase();
+
+        match self.profiles.iter_mut().find(|p| p.username == username) {
+            Some(p) => Ok(p),
+            None => Err(StrawError::NotFound),
+        }
+    }
+
+    /// Get the username of a profile by its hashed ID
+    fn username(&self, profile: &str) -> Result<String> {
+        match self.profiles.iter().find(|p| p.id == profile) {
+            Some(p) => Ok(p.username.clone()),
+            None => Err(StrawError::NotFound),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ProfileStore for MemoryStore {
+    async fn apply_migrations(
+        &self,
+        _migrations: &[Migration],
+    ) -> std::result::Result<Vec<String>, MigrationError> {
+        Ok(Vec::new())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn bootstrap_owner(&self) -> Result<bool> {
+        let owner = match self.config.owner {
+            Some(ref o) => o.to_lowercase(),
+            None => return Ok(false),
+        };
+
+        let mut state = self.state();
+
+        if state.bootstrapped {
+            return Ok(false);
+        }
+
+        let profile = match state.profile_by_username(&owner) {
+            Ok(p) => p,
+            Err(_) => return Ok(false),
+        };
+
+        // grant title, replacing the current one (and any temporary title)
+        let title: Skill = SkillName::God.into();
+        let before = profile.skills.clone();
+
+        profile.skills.retain(|s| s.0 .0 != SkillType::Title);
+        profile.skills.insert(0, title);
+
+        let skills = &profile.skills;
+        profile
+            .grants
+            .retain(|g| skills.iter().any(|s| s.0 .1 == g.name) && (g.name != SkillName::God));
+        profile
+            .grants
+            .push(SkillGrant::new(SkillName::God, &GrantContext::default()));
+
+        let entry = Actor::system()
+            .entry(&profile.username, AuditAction::Bootstrap)
+            .skills(&before, &profile.skills);
+
+        profile.version += 1;
+        state.bootstrapped = true;
+        state.audit.push(entry);
+
+        Ok(true)
+    }
+
+    // profiles
+    async fn get_skills(&self, profile: &str) -> Result<(SkillSet, Vec<SkillGrant>)> {
+        Ok(self
+            .state()
+            .profiles
+            .iter()
+            .find(|p| p.id == profile)
+            .map(|p| (p.skills.clone(), p.grants.clone()))
+            .unwrap_or_default())
+    }
+
+    // GET
+    async fn get_profile_by_hashed(&self, hashed: String) -> Result<Profile> {
+        match self.state().profiles.iter().find(|p| p.id == hashed) {
+            Some(p) => Ok(p.clone()),
+            None => Err(StrawError::Other),
+        }
+    }
+
+    async fn get_profile_by_uid(&self, uid: String) -> Result<Profile> {
+        match self.state().profiles.iter().find(|p| p.uid == uid) {
+            Some(p) => Ok(p.clone()),
+            None => Err(StrawError::NotFound),
+        }
+    }
+
+    async fn get_profile_by_unhashed_st(&self, unhashed: String) -> Result<Profile> {
+        let hashed = utility::hash(unhashed);
+        let state = self.state();
+
+        let profile = match state
+            .secondary_tokens
+            .iter()
+            .find(|(_, t)| t.token == hashed)
+        {
+            Some((p, _)) => p,
+            None => return Err(StrawError::Other),
+        };
+
+        match state.profiles.iter().find(|p| &p.id == profile) {
+            Some(p) => Ok(p.clone()),
+            None => Err(StrawError::Other),
+        }
+    }
+
+    async fn get_profile_by_username(&self, username: String) -> Result<Profile> {
+        self.state().profile_by_username(&username).cloned()
+    }
+
+    async fn get_profiles(
+        &self,
+        query: String,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Profile>> {
+        let query = query.to_lowercase();
+
+        let mut out: Vec<Profile> = self
+            .state()
+            .profiles
+            .iter()
+            .filter(|p| p.username.contains(&query))
+            .cloned()
+            .collect();
+
+        out.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(out.into_iter().skip(offset).take(limit).collect())
+    }
+
+    async fn get_profiles_with_skill(&self, skill: SkillName) -> Result<Vec<Profile>> {
+        let mut out: Vec<Profile> = self
+            .state()
+            .profiles
+            .iter()
+            .filter(|p| p.skills.iter().any(|s| s.0 .1 == skill))
+            .cloned()
+            .collect();
+
+        out.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(out)
+    }
+
+    async fn get_profiles_with_expired_skills(&self, now: u128) -> Result<Vec<Profile>> {
+        Ok(self
+            .state()
+            .profiles
+            .iter()
+            .filter(|p| p.grants.iter().any(|g| g.expired(now)))
+            .cloned()
+            .collect())
+    }
+
+    // SET
+    async fn create_profile(&self, username: String, actor: &Actor) -> Result<String> {
+        // check username
+        store::check_username(&username)?;
+
+        let user_id_unhashed: String = utility::uuid();
+
+        {
+            let mut state = self.state();
+
+            if state.profile_by_username(&username).is_ok() {
+                return Err(StrawError::MustBeUnique);
+            }
+
+            state.profiles.push(Profile {
+                id: utility::hash(user_id_unhashed.clone()),
+                uid: utility::uuid(),
+                username: username.to_lowercase(),
+                metadata: ProfileMetadata::default(),
+                skills: vec![SkillName::Normal.into()],
+                grants: vec![SkillGrant::new(SkillName::Normal, &GrantContext::default())],
+                joined: utility::unix_epoch_timestamp(),
+                version: 0,
+            });
+
+            let entry = actor.entry(&username.to_lowercase(), AuditAction::Register);
+            state.audit.push(entry);
+        }
+
+        Ok(user_id_unhashed)
+    }
+
+    async fn edit_profile_metadata_by_name(
+        &self,
+        name: String,
+        metadata: ProfileMetadata,
+        version: u64,
+        actor: &Actor,
+    ) -> Result<u64> {
+        let mut state = self.state();
+        let profile = state.profile_by_username(&name)?;
+
+        if profile.version != version {
+            return Err(StrawError::Conflict);
+        }
+
+        profile.metadata = metadata;
+        profile.version += 1;
+
+        let version = profile.version;
+        let entry = actor.entry(&profile.username, AuditAction::Metadata);
+        state.audit.push(entry);
+
+        Ok(version)
+    }
+
+    async fn get_secondary_tokens(&self, profile: String) -> Result<Vec<SecondaryToken>> {
+        let mut out: Vec<SecondaryToken> = self
+            .state()
+            .secondary_tokens
+            .iter()
+            .filter(|(p, _)| p == &profile)
+            .map(|(_, t)| t.clone())
+            .collect();
+
+        out.sort_by_key(|t| t.created);
+        Ok(out)
+    }
+
+    async fn create_secondary_token(
+        &self,
+        name: String,
+        label: String,
+        actor: &Actor,
+    ) -> Result<String> {
+        let mut state = self.state();
+        let (profile, username) = {
+            let p = state.profile_by_username(&name)?;
+            (p.id.clone(), p.username.clone())
+        };
+
+        // check label
+        if label.is_empty() | (label.len() > 64) {
+            return Err(StrawError::ValueError);
+        }
+
+        if state
+            .secondary_tokens
+            .iter()
+            .any(|(p, t)| (p == &profile) && (t.name == label))
+        {
+            return Err(StrawError::MustBeUnique);
+        }
+
+        // add token
+        let token_unhashed: String = utility::random_id();
+
+        state.secondary_tokens.push((
+            profile,
+            SecondaryToken {
+                name: label,
+                token: utility::hash(token_unhashed.clone()),
+                created: utility::unix_epoch_timestamp(),
+            },
+        ));
+
+        state
+            .audit
+            .push(actor.entry(&username, AuditAction::CreateToken));
+
+        Ok(token_unhashed)
+    }
+
+    async fn delete_secondary_token(
+        &self,
+        name: String,
+        label: String,
+        actor: &Actor,
+    ) -> Result<()> {
+        let mut state = self.state();
+        let (profile, username) = {
+            let p = state.profile_by_username(&name)?;
+            (p.id.clone(), p.username.clone())
+        };
+
+        let count = state.secondary_tokens.len();
+        state
+            .secondary_tokens
+            .retain(|(p, t)| !((p == &profile) && (t.name == label)));
+
+        if state.secondary_tokens.len() == count {
+            return Err(StrawError::NotFound);
+        }
+
+        state
+            .audit
+            .push(actor.entry(&username, AuditAction::RevokeToken));
+
+        Ok(())
+    }
+
+    async fn edit_profile_skills_by_name(
+        &self,
+        name: String,
+        skills: SkillSet,
+        grants: Vec<SkillGrant>,
+        version: u64,
+        actor: &Actor,
+        action: AuditAction,
+    ) -> Result<u64> {
+        let mut state = self.state();
+        let profile = state.profile_by_username(&name)?;
+
+        if profile.version != version {
+            return Err(StrawError::Conflict);
+        }
+
+        let entry = actor
+            .entry(&profile.username, action)
+            .skills(&profile.skills, &skills);
+
+        profile.skills = skills;
+        profile.grants = grants;
+        profile.version += 1;
+
+        let version = profile.version;
+        state.audit.push(entry);
+
+        Ok(version)
+    }
+
+    async fn rotate_profile_key(&self, name: String, actor: &Actor) -> Result<String> {
+        let mut state = self.state();
+
+        let user_id_unhashed: String = utility::uuid();
+        let user_id_hashed: String = utility::hash(user_id_unhashed.clone());
+
+        // ...
+        let profile = state.profile_by_username(&name)?;
+        let old = std::mem::replace(&mut profile.id, user_id_hashed.clone());
+        let entry = actor.entry(&profile.username, AuditAction::RotateKey);
+
+        for key in state.api_keys.iter_mut().filter(|k| k.profile == old) {
+            key.profile = user_id_hashed.clone();
+        }
+
+        for (p, _) in state.secondary_tokens.iter_mut().filter(|(p, _)| p == &old) {
+            *p = user_id_hashed.clone();
+        }
+
+        for (_, p, _) in state.handoffs.iter_mut().filter(|(_, p, _)| p == &old) {
+            *p = user_id_hashed.clone();
+        }
+
+        for client in state.oauth_clients.iter_mut().filter(|c| c.owner == old) {
+            client.owner = user_id_hashed.clone();
+        }
+
+        for code in state.oauth_codes.iter_mut().filter(|c| c.profile == old) {
+            code.profile = user_id_hashed.clone();
+        }
+
+        for token in state.oauth_tokens.iter_mut().filter(|t| t.profile == old) {
+            token.profile = user_id_hashed.clone();
+        }
+
+        for grant in state
+            .profiles
+            .iter_mut()
+            .flat_map(|p| p.grants.iter_mut())
+            .filter(|g| g.granted_by == old)
+        {
+            grant.granted_by = user_id_hashed.clone();
+        }
+
+        state.sessions.retain(|s| s.profile != old);
+        state.audit.push(entry);
+
+        // return
+        Ok(user_id_unhashed)
+    }
+
+    async fn delete_profile_by_name(&self, name: String, actor: &Actor) -> Result<()> {
+        let mut state = self.state();
+        let (id, username) = {
+            let p = state.profile_by_username(&name)?;
+            (p.id.clone(), p.username.clone())
+        };
+
+        state.sessions.retain(|s| s.profile != id);
+        state.api_keys.retain(|k| k.profile != id);
+        state.handoffs.retain(|(_, p, _)| p != &id);
+        state.oauth_codes.retain(|c| c.profile != id);
+        state.oauth_tokens.retain(|t| t.profile != id);
+        state.oauth_clients.retain(|c| c.owner != id);
+        state.secondary_tokens.retain(|(p, _)| p != &id);
+        state.profiles.retain(|p| p.id != id);
+        state
+            .audit
+            .push(actor.entry(&username, AuditAction::Delete));
+
+        Ok(())
+    }
+
+    // sessions
+
+    // GET
+    async fn get_session_by_unhashed(&self, unhashed: String) -> Result<Session> {
+        let hashed = utility::hash(unhashed);
+
+        match self.state().sessions.iter().find(|s| s.token == hashed) {
+            Some(s) => Ok(s.clone()),
+            None => Err(StrawError::NotFound),
+        }
+    }
+
+    async fn get_sessions_by_profile(&self, profile: String) -> Result<Vec<Session>> {
+        let mut out: Vec<Session> = self
+            .state()
+            .sessions
+            .iter()
+            .filter(|s| s.profile == profile)
+            .cloned()
+            .collect();
+
+        out.sort_by_key(|s| std::cmp::Reverse(s.last_used));
+        Ok(out)
+    }
+
+    async fn get_profile_by_session(&self, unhashed: String) -> Result<(Profile, Session)> {
+        let session = self.get_session_by_unhashed(unhashed).await?;
+        let profile = self.get_profile_by_hashed(session.profile.clone()).await?;
+
+        // update last used
+        if let Some(s) = self
+            .state()
+            .sessions
+            .iter_mut()
+            .find(|s| s.id == session.id)
+        {
+            s.last_used = utility::unix_epoch_timestamp();
+        }
+
+        // return
+        Ok((profile, session))
+    }
+
+    // SET
+    async fn create_session(
+        &self,
+        profile: String,
+        user_agent: String,
+        ip: String,
+    ) -> Result<String> {
+        let token_unhashed: String = utility::random_id();
+        let timestamp = utility::unix_epoch_timestamp();
+
+        let mut state = self.state();
+        let username = state.username(&profile)?;
+        let entry = Actor::new(&username)
+            .ip(&ip)
+            .entry(&username, AuditAction::Login);
+
+        state.sessions.push(Session {
+            id: utility::uuid(),
+            token: utility::hash(token_unhashed.clone()),
+            profile,
+            created: timestamp,
+            last_used: timestamp,
+            user_agent,
+            ip,
+        });
+
+        state.audit.push(entry);
+        Ok(token_unhashed)
+    }
+
+    async fn delete_session(&self, profile: String, id: String, actor: &Actor) -> Result<()> {
+        let mut state = self.state();
+        let username = state.username(&profile)?;
+
+        let count = state.sessions.len();
+        state
+            .sessions
+            .retain(|s| !((s.profile == profile) && (s.id == id)));
+
+        if state.sessions.len() == count {
+            return Err(StrawError::NotFound);
+        }
+
+        state
+            .audit
+            .push(actor.entry(&username, AuditAction::Logout));
+        Ok(())
+    }
+
+    async fn delete_sessions_by_profile(&self, profile: String, actor: &Actor) -> Result<()> {
+        let mut state = self.state();
+        let username = state.username(&profile)?;
+
+        state.sessions.retain(|s| s.profile != profile);
+        state
+            .audit
+            .push(actor.entry(&username, AuditAction::Logout));
+        Ok(())
+    }
+
+    // api keys
+
+    // GET
+    async fn get_api_key_by_unhashed(&self, unhashed: String) -> Result<ApiKey> {
+        let hashed = utility::hash(unhashed);
+
+        let key = match self.state().api_keys.iter().find(|k| k.token == hashed) {
+            Some(k) => k.clone(),
+            None => return Err(StrawError::NotFound),
+        };
+
+        // check expiry
+        if (key.expires != 0) && (utility::unix_epoch_timestamp() > key.expires) {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(key)
+    }
+
+    async fn get_api_keys_by_profile(&self, profile: String) -> Result<Vec<ApiKey>> {
+        Ok(self
+            .state()
+            .api_keys
+            .iter()
+            .filter(|k| k.profile == profile)
+            .cloned()
+            .collect())
+    }
+
+    // SET
+    async fn create_api_key(
+        &self,
+        profile: String,
+        label: String,
+        scope: ApiKeyScope,
+        expires_in: Option<u128>,
+        actor: &Actor,
+    ) -> Result<(ApiKey, String)> {
+        // check values
+        if label.is_empty() | (label.len() > 64) {
+            return Err(StrawError::ValueError);
+        }
+
+        if scope.max_power.map(|p| p < 0.0).unwrap_or(false) {
+            return Err(StrawError::ValueError);
+        }
+
+        // ...
+        let token_unhashed: String = utility::random_id();
+        let timestamp = utility::unix_epoch_timestamp();
+
+        let key = ApiKey {
+            id: utility::uuid(),
+            token: utility::hash(token_unhashed.clone()),
+            profile,
+            label,
+            scope,
+            created: timestamp,
+            expires: match expires_in {
+                Some(e) => timestamp + e,
+                None => 0,
+            },
+        };
+
+        let mut state = self.state();
+        let username = state.username(&key.profile)?;
+
+        state.api_keys.push(key.clone());
+        state
+            .audit
+            .push(actor.entry(&username, AuditAction::CreateToken));
+
+        Ok((key, token_unhashed))
+    }
+
+    async fn delete_api_key(&self, profile: String, id: String, actor: &Actor) -> Result<()> {
+        let mut state = self.state();
+        let username = state.username(&profile)?;
+
+        let count = state.api_keys.len();
+        state
+            .api_keys
+            .retain(|k| !((k.profile == profile) && (k.id == id)));
+
+        if state.api_keys.len() == count {
+            return Err(StrawError::NotFound);
+        }
+
+        state
+            .audit
+            .push(actor.entry(&username, AuditAction::RevokeToken));
+
+        Ok(())
+    }
+
+    // handoffs
+
+    // SET
+    async fn create_handoff_code(&self, profile: String) -> Result<String> {
+        let code_unhashed: String = utility::random_id();
+        let timestamp = utility::unix_epoch_timestamp();
+
+        let mut state = self.state();
+
+        // remove expired codes
+        state
+            .handoffs
+            .retain(|(_, _, created)| timestamp.saturating_sub(*created) <= HANDOFF_CODE_LIFETIME);
+
+        // ...
+        state
+            .handoffs
+            .push((utility::hash(code_unhashed.clone()), profile, timestamp));
+
+        Ok(code_unhashed)
+    }
+
+    async fn consume_handoff_code(&self, unhashed: String) -> Result<String> {
+        let hashed = utility::hash(unhashed);
+        let mut state = self.state();
+
+        let (_, profile, created) = match state.handoffs.iter().position(|(c, _, _)| c == &hashed) {
+            Some(i) => state.handoffs.remove(i),
+            None => return Err(StrawError::NotFound),
+        };
+
+        // check expiry
+        if utility::unix_epoch_timestamp().saturating_sub(created) > HANDOFF_CODE_LIFETIME {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(profile)
+    }
+
+    // oauth
+
+    // GET
+    async fn get_oauth_client(&self, id: String) -> Result<OAuthClient> {
+        match self.state().oauth_clients.iter().find(|c| c.id == id) {
+            Some(c) => Ok(c.clone()),
+            None => Err(StrawError::NotFound),
+        }
+    }
+
+    async fn get_oauth_code(&self, unhashed: String) -> Result<OAuthCode> {
+        let hashed = utility::hash(unhashed);
+
+        let code = match self.state().oauth_codes.iter().find(|c| c.code == hashed) {
+            Some(c) => c.clone(),
+            None => return Err(StrawError::NotFound),
+        };
+
+        // check expiry
+        if utility::unix_epoch_timestamp().saturating_sub(code.created) > OAUTH_CODE_LIFETIME {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(code)
+    }
+
+    async fn get_oauth_token_by_unhashed(&self, unhashed: String) -> Result<OAuthToken> {
+        let hashed = utility::hash(unhashed);
+
+        let token = match self.state().oauth_tokens.iter().find(|t| t.token == hashed) {
+            Some(t) => t.clone(),
+            None => return Err(StrawError::NotFound),
+        };
+
+        // check expiry
+        if utility::unix_epoch_timestamp() > token.expires {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(token)
+    }
+
+    // SET
+    async fn create_oauth_client(
+        &self,
+        owner: String,
+        name: String,
+        redirect_uris: Vec<String>,
+        confidential: bool,
+        actor: &Actor,
+    ) -> Result<(OAuthClient, String)> {
+        // check values
+        store::check_oauth_client(&name, &redirect_uris)?;
+
+        // ...
+        let secret_unhashed: String = if confidential {
+            utility::random_id()
+        } else {
+            String::new()
+        };
+
+        let client = OAuthClient {
+            id: utility::uuid(),
+            secret: if confidential {
+                utility::hash(secret_unhashed.clone())
+            } else {
+                String::new()
+            },
+            name,
+            redirect_uris,
+            owner,
+            created: utility::unix_epoch_timestamp(),
+        };
+
+        let mut state = self.state();
+        let username = state.username(&client.owner)?;
+
+        state.oauth_clients.push(client.clone());
+        state
+            .audit
+            .push(actor.entry(&username, AuditAction::CreateClient));
+
+        Ok((client, secret_unhashed))
+    }
+
+    async fn create_oauth_code(&self, mut code: OAuthCode) -> Result<String> {
+        let code_unhashed: String = utility::random_id();
+        code.code = utility::hash(code_unhashed.clone());
+        code.created = utility::unix_epoch_timestamp();
+
+        let mut state = self.state();
+
+        // remove expired codes
+        state
+            .oauth_codes
+            .retain(|c| code.created.saturating_sub(c.created) <= OAUTH_CODE_LIFETIME);
+
+        // ...
+        state.oauth_codes.push(code);
+        Ok(code_unhashed)
+    }
+
+    async fn consume_oauth_code(&self, unhashed: String) -> Result<OAuthCode> {
+        let hashed = utility::hash(unhashed);
+        let mut state = self.state();
+
+        let code = match state.oauth_codes.iter().position(|c| c.code == hashed) {
+            Some(i) => state.oauth_codes.remove(i),
+            None => return Err(StrawError::NotFound),
+        };
+
+        // check expiry
+        if utility::unix_epoch_timestamp().saturating_sub(code.created) > OAUTH_CODE_LIFETIME {
+            return Err(StrawError::NotAllowed);
+        }
+
+        // return
+        Ok(code)
+    }
+
+    async fn create_oauth_token(
+        &self,
+        client: String,
+        profile: String,
+        scope: String,
+    ) -> Result<(OAuthToken, String)> {
+        let token_unhashed: String = utility::random_id();
+        let timestamp = utility::unix_epoch_timestamp();
+
+        let token = OAuthToken {
+            token: utility::hash(token_unhashed.clone()),
+            client,
+            profile,
+            scope,
+            created: timestamp,
+            expires: timestamp + OAUTH_TOKEN_LIFETIME,
+        };
+
+        let mut state = self.state();
+        let username = state.username(&token.profile)?;
+
+        // remove expired tokens
+        state.oauth_tokens.retain(|t| t.expires >= timestamp);
+
+        // ...
+        state.oauth_tokens.push(token.clone());
+        state
+            .audit
+            .push(Actor::new(&username).entry(&username, AuditAction::Login));
+
+        Ok((token, token_unhashed))
+    }
+
+    // keys
+
+    // GET
+    async fn get_signing_keys(&self) -> Result<Vec<SigningKey>> {
+        let mut out = self.state().keys.clone();
+        out.sort_by_key(|k| std::cmp::Reverse(k.created));
+        Ok(out)
+    }
+
+    // SET
+    async fn create_signing_key(&self) -> Result<SigningKey> {
+        let key = store::generate_signing_key()?;
+        self.state().keys.push(key.clone());
+        Ok(key)
+    }
+
+    async fn rotate_signing_keys(&self) -> Result<()> {
+        let keys = self.get_signing_keys().await?;
+        let now = utility::unix_epoch_timestamp();
+
+        // delete keys that have been replaced for longer than the token lifetime (a key
+        // signs tokens until the next key is created, however late that was)
+        let expired: Vec<&String> = keys
+            .windows(2)
+            .filter(|pair| now > pair[0].created + self.config.jwt_lifetime)
+            .map(|pair| &pair[1].id)
+            .collect();
+
+        self.state().keys.retain(|k| !expired.contains(&&k.id));
+
+        // create a new key if the current one has been used for long enough
+        if keys
+            .first()
+            .map(|k| now >= k.created + self.config.key_rotation_interval)
+            .unwrap_or(true)
+        {
+            self.create_signing_key().await?;
+        }
+
+        Ok(())
+    }
+
+    // audit
+    // GET
+    async fn get_audit_entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let mut entries: Vec<AuditEntry> = self
+            .state()
+            .audit
+            .iter()
+            .filter(|e| query.matches(e))
+            .cloned()
+            .collect();
+
+        // newest first, the same order the SQL store uses
+        entries.sort_by(|a, b| (b.timestamp, &b.id).cmp(&(a.timestamp, &a.id)));
+
+        Ok(entries
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.min(500))
+            .collect())
+    }
+
+    // SET
+    async fn create_audit_entry(&self, entry: AuditEntry) -> Result<()> {
+        self.state().audit.push(entry);
+        Ok(())
+    }
+}
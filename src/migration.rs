@@ -0,0 +1,326 @@
+//! Versioned schema migrations
+//!
+//! Every [`Migration`] is applied once, in order, by [`Database::init`](crate::Database::init).
+//! Applied migrations are recorded in the `sr_migrations` table by their `id`.
+//!
+//! Host apps can append their own migrations before calling `init`:
+//!
+//! ```ignore
+//! let mut database = Database::new(Database::env_options(), ServerOptions::truthy()).await;
+//!
+//! database.add_migration(Migration::new(
+//!     "myapp_0001_create_pastes",
+//!     &["CREATE TABLE \"myapp_pastes\" (id TEXT, content TEXT)"],
+//! ));
+//!
+//! database.init().await.unwrap();
+//! ```
+use std::fmt::Display;
+
+/// A single schema change, with statements for every SQL dialect
+#[derive(Clone, Debug)]
+pub struct Migration {
+    /// A unique name for the migration, host apps should prefix their own
+    /// (`"myapp_0001_create_pastes"`)
+    pub id: String,
+    pub sqlite: Vec<String>,
+    pub postgres: Vec<String>,
+    pub mysql: Vec<String>,
+    /// A data conversion which is run before the statements
+    pub(crate) conversion: Option<Conversion>,
+}
+
+/// Data conversions which can't be expressed in SQL for every dialect
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Conversion {
+    /// Copy the JSON `sr_profiles.skills` column into `sr_skills`
+    SkillsToTable,
+    /// Move secondary tokens out of `sr_profiles.metadata` into `sr_secondary_tokens`
+    SecondaryTokensToTable,
+    /// Give every profile a random `sr_profiles.uid`
+    ProfileUids,
+    /// Fail with the duplicated usernames before `sr_profiles.username` is made unique
+    UniqueUsernames,
+}
+
+impl Migration {
+    /// Create a new [`Migration`] which runs the same statements on every dialect
+    pub fn new(id: &str, statements: &[&str]) -> Self {
+        let statements: Vec<String> = statements.iter().map(|s| s.to_string()).collect();
+
+        Self {
+            id: id.to_string(),
+            sqlite: statements.clone(),
+            postgres: statements.clone(),
+            mysql: statements,
+            conversion: None,
+        }
+    }
+
+    /// Replace the statements which are run on sqlite
+    pub fn sqlite(mut self, statements: &[&str]) -> Self {
+        self.sqlite = statements.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Replace the statements which are run on postgres
+    pub fn postgres(mut self, statements: &[&str]) -> Self {
+        self.postgres = statements.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Replace the statements which are run on mysql
+    pub fn mysql(mut self, statements: &[&str]) -> Self {
+        self.mysql = statements.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Get the statements for the given dialect (`"sqlite"`, `"postgres"` or `"mysql"`)
+    pub fn statements(&self, dialect: &str) -> &Vec<String> {
+        match dialect {
+            "sqlite" => &self.sqlite,
+            "mysql" => &self.mysql,
+            _ => &self.postgres,
+        }
+    }
+}
+
+/// A [`Migration`] which could not be applied
+///
+/// Migrations are applied in a transaction, so a failed migration leaves nothing
+/// behind (except on mysql, where schema changes commit implicitly).
+#[derive(Clone, Debug)]
+pub struct MigrationError {
+    /// The `id` of the failed migration, empty if the `sr_migrations` table itself
+    /// could not be created
+    pub id: String,
+    /// The error reported by the database
+    pub error: String,
+}
+
+impl MigrationError {
+    pub(crate) fn new(id: &str, error: impl Display) -> Self {
+        Self {
+            id: id.to_string(),
+            error: error.to_string(),
+        }
+    }
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.id.is_empty() {
+            write!(f, "failed to prepare migrations: {}", self.error)
+        } else {
+            write!(f, "migration \"{}\" failed: {}", self.id, self.error)
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+/// The migrations which make up the starstraw schema, in order
+pub fn builtin() -> Vec<Migration> {
+    vec![
+        Migration::new(
+            "0001_initial",
+            &[
+                "CREATE TABLE IF NOT EXISTS \"sr_profiles\" (
+                id       TEXT,
+                username TEXT,
+                metadata TEXT,
+                joined   TEXT,
+                skills   TEXT
+            )",
+                "CREATE TABLE IF NOT EXISTS \"sr_sessions\" (
+                id         TEXT,
+                token      TEXT,
+                profile    TEXT,
+                created    TEXT,
+                last_used  TEXT,
+                user_agent TEXT,
+                ip         TEXT
+            )",
+                "CREATE TABLE IF NOT EXISTS \"sr_api_keys\" (
+                id      TEXT,
+                token   TEXT,
+                profile TEXT,
+                label   TEXT,
+                scope   TEXT,
+                created TEXT,
+                expires TEXT
+            )",
+                "CREATE TABLE IF NOT EXISTS \"sr_handoffs\" (
+                code    TEXT,
+                profile TEXT,
+                created TEXT
+            )",
+                "CREATE TABLE IF NOT EXISTS \"sr_oauth_clients\" (
+                id            TEXT,
+                secret        TEXT,
+                name          TEXT,
+                redirect_uris TEXT,
+                owner         TEXT,
+                created       TEXT
+            )",
+                "CREATE TABLE IF NOT EXISTS \"sr_oauth_codes\" (
+                code         TEXT,
+                client       TEXT,
+                profile      TEXT,
+                redirect_uri TEXT,
+                scope        TEXT,
+                challenge    TEXT,
+                nonce        TEXT,
+                created      TEXT
+            )",
+                "CREATE TABLE IF NOT EXISTS \"sr_oauth_tokens\" (
+                token   TEXT,
+                client  TEXT,
+                profile TEXT,
+                scope   TEXT,
+                created TEXT,
+                expires TEXT
+            )",
+                "CREATE TABLE IF NOT EXISTS \"sr_keys\" (
+                id      TEXT,
+                private TEXT,
+                public  TEXT,
+                created TEXT
+            )",
+                "CREATE TABLE IF NOT EXISTS \"sr_state\" (
+                name  TEXT,
+                value TEXT
+            )",
+            ],
+        ),
+        Migration::new(
+            "0002_skills_table",
+            &[
+                "CREATE TABLE IF NOT EXISTS \"sr_skills\" (
+                profile    VARCHAR(255),
+                position   TEXT,
+                skill_type VARCHAR(255),
+                name       VARCHAR(255),
+                value      TEXT,
+                granted_at TEXT,
+                granted_by TEXT
+            )",
+                "CREATE INDEX \"sr_skills_profile\" ON \"sr_skills\" (profile)",
+                "CREATE INDEX \"sr_skills_name\" ON \"sr_skills\" (name)",
+            ],
+        ),
+        Migration {
+            conversion: Some(Conversion::SkillsToTable),
+            ..Migration::new(
+                "0003_skills_from_json",
+                &["ALTER TABLE \"sr_profiles\" DROP COLUMN skills"],
+            )
+        },
+        Migration::new(
+            "0004_secondary_tokens_table",
+            &[
+                "CREATE TABLE IF NOT EXISTS \"sr_secondary_tokens\" (
+                    token   VARCHAR(255),
+                    profile VARCHAR(255),
+                    name    VARCHAR(255),
+                    created TEXT
+                )",
+                "CREATE UNIQUE INDEX \"sr_secondary_tokens_token\" ON \"sr_secondary_tokens\" (token)",
+                "CREATE UNIQUE INDEX \"sr_secondary_tokens_name\" ON \"sr_secondary_tokens\" (profile, name)",
+            ],
+        ),
+        Migration {
+            conversion: Some(Conversion::SecondaryTokensToTable),
+            ..Migration::new("0005_secondary_tokens_from_metadata", &[])
+        },
+        // sqlite can't add a primary key to an existing table, so it is rebuilt
+        Migration {
+            conversion: Some(Conversion::UniqueUsernames),
+            ..Migration::new(
+                "0006_profile_constraints",
+                &[
+                    "ALTER TABLE \"sr_profiles\" ADD PRIMARY KEY (id)",
+                    "CREATE UNIQUE INDEX \"sr_profiles_username\" ON \"sr_profiles\" (username)",
+                ],
+            )
+            .sqlite(&[
+                "CREATE TABLE \"sr_profiles_new\" (
+                    id       TEXT PRIMARY KEY NOT NULL,
+                    username TEXT NOT NULL,
+                    metadata TEXT,
+                    joined   TEXT
+                )",
+                "INSERT INTO \"sr_profiles_new\" (id, username, metadata, joined)
+                    SELECT id, username, metadata, joined FROM \"sr_profiles\"",
+                "DROP TABLE \"sr_profiles\"",
+                "ALTER TABLE \"sr_profiles_new\" RENAME TO \"sr_profiles\"",
+                "CREATE UNIQUE INDEX \"sr_profiles_username\" ON \"sr_profiles\" (username)",
+            ])
+            .mysql(&[
+                "ALTER TABLE \"sr_profiles\" MODIFY id VARCHAR(255) NOT NULL, MODIFY username VARCHAR(500) NOT NULL",
+                "ALTER TABLE \"sr_profiles\" ADD PRIMARY KEY (id)",
+                "CREATE UNIQUE INDEX \"sr_profiles_username\" ON \"sr_profiles\" (username)",
+            ])
+        },
+        Migration::new(
+            "0007_profile_version",
+            &["ALTER TABLE \"sr_profiles\" ADD COLUMN version VARCHAR(32) NOT NULL DEFAULT '0'"],
+        ),
+        Migration::new(
+            "0008_skill_expiry",
+            &["ALTER TABLE \"sr_skills\" ADD COLUMN expires_at VARCHAR(32) NOT NULL DEFAULT ''"],
+        ),
+        Migration::new(
+            "0009_skill_reason",
+            &["ALTER TABLE \"sr_skills\" ADD COLUMN reason VARCHAR(1000) NOT NULL DEFAULT ''"],
+        ),
+        Migration::new(
+            "0010_audit_log",
+            &[
+                "CREATE TABLE IF NOT EXISTS \"sr_audit\" (
+                    id            VARCHAR(255) NOT NULL,
+                    actor         VARCHAR(500) NOT NULL,
+                    target        VARCHAR(500) NOT NULL,
+                    action        VARCHAR(32) NOT NULL,
+                    skills_before TEXT NOT NULL,
+                    skills_after  TEXT NOT NULL,
+                    created       VARCHAR(32) NOT NULL,
+                    ip            VARCHAR(255) NOT NULL
+                )",
+                "CREATE INDEX \"sr_audit_created\" ON \"sr_audit\" (created)",
+                "CREATE INDEX \"sr_audit_actor\" ON \"sr_audit\" (actor)",
+                "CREATE INDEX \"sr_audit_target\" ON \"sr_audit\" (target)",
+            ],
+        ),
+        Migration::new(
+            "0011_profile_uid",
+            &["ALTER TABLE \"sr_profiles\" ADD COLUMN uid VARCHAR(255) NOT NULL DEFAULT ''"],
+        ),
+        Migration {
+            conversion: Some(Conversion::ProfileUids),
+            ..Migration::new(
+                "0012_profile_uid_values",
+                &["CREATE UNIQUE INDEX \"sr_profiles_uid\" ON \"sr_profiles\" (uid)"],
+            )
+        },
+        Migration::new(
+            "0013_audit_reason",
+            &["ALTER TABLE \"sr_audit\" ADD COLUMN reason VARCHAR(1000) NOT NULL DEFAULT ''"],
+        ),
+        // every authenticated request looks up one of these by token
+        // (mysql can't index TEXT columns, so they're made VARCHAR first)
+        Migration::new(
+            "0014_token_indexes",
+            &[
+                "CREATE INDEX \"sr_sessions_token\" ON \"sr_sessions\" (token)",
+                "CREATE INDEX \"sr_api_keys_token\" ON \"sr_api_keys\" (token)",
+            ],
+        )
+        .mysql(&[
+            "ALTER TABLE \"sr_sessions\" MODIFY token VARCHAR(255)",
+            "ALTER TABLE \"sr_api_keys\" MODIFY token VARCHAR(255)",
+            "CREATE INDEX \"sr_sessions_token\" ON \"sr_sessions\" (token)",
+            "CREATE INDEX \"sr_api_keys_token\" ON \"sr_api_keys\" (token)",
+        ]),
+    ]
+}
@@ -5,6 +5,7 @@ use axum::{
 };
 
 use crate::database::Result;
+use crate::registry::SkillRegistry;
 use dorsal::DefaultReturn;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -58,10 +59,28 @@ pub enum SkillName {
     Manager,
     /// `Title` type skill; multiplies all skill levels by 1
     Normal,
+    // custom
+    /// A skill defined by the host app in a [`SkillRegistry`], serialized as just its name
+    ///
+    /// Names of built-in skills always deserialize to their own variant, so
+    /// `Custom("God")` should never be constructed.
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl From<&str> for SkillName {
+    /// Get a [`SkillName`] from its name, built-in names get their own variant
+    fn from(value: &str) -> Self {
+        serde_json::from_value(serde_json::Value::String(value.to_string()))
+            .unwrap_or(SkillName::Custom(value.to_string()))
+    }
 }
 
 impl Into<Skill> for SkillName {
-    /// Get a skill and its values from just its name
+    /// Get a built-in skill and its values from just its name
+    ///
+    /// Custom skills aren't known here, so they're returned as an `Ability` with a value
+    /// of 1. Use [`SkillRegistry::skill`] to get any registered skill.
     fn into(self) -> Skill {
         use SkillName::*;
         match self {
@@ -78,27 +97,12 @@ impl Into<Skill> for SkillName {
             Administrator => ((SkillType::Title, self), 10_000.0),
             Manager => ((SkillType::Title, self), 1_000.0),
             Normal => ((SkillType::Title, self), 1.0),
+            // custom
+            Custom(_) => ((SkillType::Ability, self), 1.0),
         }
     }
 }
 
-impl SkillName {
-    /// Check if a skill is valid based on other skills or the user's overall levels
-    pub fn is_valid(&self, stats: ProfileStats) -> bool {
-        // check if the skill is valid
-        if (self == &SkillName::Absolute) && (stats.power < 100_000.0) {
-            // we must have a power level of at least 100,000 to get absolute power
-            return false;
-        } else if self == &SkillName::God {
-            // "God" title cannot be granted at all
-            return false;
-        }
-
-        // by default it's valid!
-        true
-    }
-}
-
 /// A basic skill - the `f32` skill number is usually the default value,
 /// but it can be set to something else when the skill is granted if the skill
 /// is a different level than its default value (default * level)
@@ -107,43 +111,492 @@ pub type SkillSet = Vec<Skill>;
 /// Only what's needed to identify a skill
 pub type SkillIdentifier = (SkillType, SkillName);
 
+/// Who granted a skill to a profile, when, and why
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SkillGrant {
+    pub name: SkillName,
+    /// The hashed ID of the profile which granted the skill, empty if it was granted
+    /// outside of the API (or before grants were recorded)
+    #[serde(default)]
+    pub granted_by: String,
+    #[serde(default)]
+    pub granted_at: u128,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// When the skill stops counting towards the profile's stats (and is removed by
+    /// [`Database::expire_skills`](crate::Database::expire_skills))
+    #[serde(default)]
+    pub expires_at: Option<u128>,
+}
+
+impl SkillGrant {
+    /// Create a new [`SkillGrant`] for the given skill, granted now
+    pub fn new(name: SkillName, context: &GrantContext) -> Self {
+        Self {
+            name,
+            granted_by: context.granted_by.clone(),
+            granted_at: dorsal::utility::unix_epoch_timestamp(),
+            reason: context.reason.clone(),
+            expires_at: context.expires_at,
+        }
+    }
+
+    /// Check if the skill has expired
+    pub fn expired(&self, now: u128) -> bool {
+        self.expires_at.map(|e| e <= now).unwrap_or(false)
+    }
+}
+
+/// Who is granting (or revoking) a skill, and why
+#[derive(Clone, Debug, Default)]
+pub struct GrantContext {
+    /// The hashed ID of the acting profile, empty for changes made outside of the API
+    pub granted_by: String,
+    pub reason: Option<String>,
+    /// When a granted skill is removed again, never if `None`
+    pub expires_at: Option<u128>,
+}
+
+impl GrantContext {
+    /// Create a new [`GrantContext`] for the given profile
+    ///
+    /// # Arguments:
+    /// * `granted_by` - `String` of the acting profile's hashed ID
+    pub fn by(granted_by: &str) -> Self {
+        Self {
+            granted_by: granted_by.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Set the reason for the change
+    pub fn reason(mut self, reason: Option<String>) -> Self {
+        self.reason = reason.filter(|r| !r.is_empty());
+        self
+    }
+
+    /// Set when granted skills are removed again
+    pub fn expires_at(mut self, expires_at: Option<u128>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// Check that the reason fits in storage and the skill doesn't expire in the past
+    fn check(&self) -> Result<()> {
+        if self
+            .reason
+            .as_ref()
+            .is_some_and(|r| r.chars().count() > 1000)
+            | self
+                .expires_at
+                .is_some_and(|e| e <= dorsal::utility::unix_epoch_timestamp())
+        {
+            return Err(StrawError::ValueError);
+        }
+
+        Ok(())
+    }
+}
+
 /// Basic user structure
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Profile {
     pub id: String,
+    /// A public ID which is kept when the profile's key is rotated, used as the
+    /// `sub` of issued tokens
+    #[serde(default)]
+    pub uid: String,
     pub username: String,
     pub metadata: ProfileMetadata,
     pub skills: SkillSet,
+    /// A [`SkillGrant`] for every skill in `skills` (except skills held since before
+    /// grants were recorded)
+    #[serde(default)]
+    pub grants: Vec<SkillGrant>,
     pub joined: u128,
+    /// Incremented every time the profile's skills or metadata are changed
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Default for Profile {
     fn default() -> Self {
         Self {
             id: String::new(),
+            uid: String::new(),
             username: String::new(),
             metadata: ProfileMetadata::default(),
             skills: [SkillName::Normal.into()].to_vec(),
+            grants: Vec::new(),
             joined: dorsal::utility::unix_epoch_timestamp(),
+            version: 0,
+        }
+    }
+}
+
+/// Extra profile information
+///
+/// Secondary tokens used to be stored here, they are now in `sr_secondary_tokens`
+/// ([`Database::get_secondary_tokens`](crate::Database::get_secondary_tokens)).
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProfileMetadata {}
+
+/// A named secondary token
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SecondaryToken {
+    /// The name of the token, unique per profile
+    pub name: String,
+    /// The hashed token
+    pub token: String,
+    pub created: u128,
+}
+
+/// A login session on a single device
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    /// The public ID of the session
+    pub id: String,
+    /// The hashed session token
+    #[serde(skip_serializing, default)]
+    pub token: String,
+    /// The hashed ID of the profile the session belongs to
+    #[serde(skip_serializing, default)]
+    pub profile: String,
+    pub created: u128,
+    pub last_used: u128,
+    pub user_agent: String,
+    pub ip: String,
+}
+
+/// An application which can authenticate profiles through OAuth
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OAuthClient {
+    /// The public `client_id` of the application
+    pub id: String,
+    /// The hashed client secret, empty for public clients
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub name: String,
+    /// The exact redirect URIs the application is allowed to use
+    pub redirect_uris: Vec<String>,
+    /// The hashed ID of the profile which registered the application
+    #[serde(skip_serializing, default)]
+    pub owner: String,
+    pub created: u128,
+}
+
+/// A single-use OAuth authorization code
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OAuthCode {
+    /// The hashed code
+    pub code: String,
+    pub client: String,
+    /// The hashed ID of the profile which authorized the client
+    pub profile: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    /// The PKCE `code_challenge` (S256), empty if none was given
+    pub challenge: String,
+    pub nonce: String,
+    pub created: u128,
+}
+
+/// An OAuth access token
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OAuthToken {
+    /// The hashed token
+    pub token: String,
+    pub client: String,
+    /// The hashed ID of the profile the token was issued for
+    pub profile: String,
+    pub scope: String,
+    pub created: u128,
+    pub expires: u128,
+}
+
+/// A key which lets machine clients act as a profile with a restricted skill set
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiKey {
+    pub id: String,
+    /// The hashed key
+    #[serde(skip_serializing, default)]
+    pub token: String,
+    /// The hashed ID of the profile which owns the key
+    #[serde(skip_serializing, default)]
+    pub profile: String,
+    pub label: String,
+    pub scope: ApiKeyScope,
+    pub created: u128,
+    /// When the key expires, `0` if it never expires
+    pub expires: u128,
+}
+
+/// The subset of a profile's skills an [`ApiKey`] can use
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ApiKeyScope {
+    /// Allowed skill types, all types are allowed if empty
+    #[serde(default)]
+    pub skill_types: Vec<SkillType>,
+    /// Allowed skills, all skills are allowed if empty
+    #[serde(default)]
+    pub skills: Vec<SkillName>,
+    /// The highest power level the key can act with
+    ///
+    /// Capped keys never carry the `God` title, and the level of their title is
+    /// lowered until their power is within the cap.
+    #[serde(default)]
+    pub max_power: Option<f32>,
+}
+
+impl ApiKeyScope {
+    /// Restrict the given [`SkillSet`] to the scope
+    pub fn restrict(&self, skills: SkillSet) -> SkillSet {
+        let mut skills: SkillSet = skills
+            .into_iter()
+            .filter(|s| self.skill_types.is_empty() | self.skill_types.contains(&s.0 .0))
+            .filter(|s| self.skills.is_empty() | self.skills.contains(&s.0 .1))
+            .collect();
+
+        // cap power
+        if let Some(max_power) = self.max_power {
+            skills.retain(|s| s.0 .1 != SkillName::God);
+            let power = SkillManager::new(skills.clone()).get_stats().power;
+
+            if power > max_power {
+                if let Some(title) = skills.iter_mut().find(|s| s.0 .0 == SkillType::Title) {
+                    title.1 *= max_power / power;
+                }
+            }
+        }
+
+        skills
+    }
+}
+
+/// An Ed25519 key used to sign JWTs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SigningKey {
+    /// The `kid` of the key
+    pub id: String,
+    /// The base64 encoded PKCS#8 private key
+    #[serde(skip_serializing, default)]
+    pub private: String,
+    /// The base64url encoded public key
+    pub public: String,
+    pub created: u128,
+}
+
+/// A kind of change recorded in the audit log
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    /// A profile was created
+    Register,
+    /// A session or OAuth access token was created
+    Login,
+    /// A session was removed
+    Logout,
+    /// A skill was granted ([`SkillManager::push`])
+    Grant,
+    /// A skill was revoked ([`SkillManager::remove`])
+    Revoke,
+    /// The title was changed ([`SkillManager::title`])
+    Title,
+    /// Expired skills were removed ([`SkillManager::expire`])
+    Expire,
+    /// The owner was granted the "God" title
+    Bootstrap,
+    /// The profile's ID was replaced
+    RotateKey,
+    /// A secondary token or API key was created
+    CreateToken,
+    /// A secondary token or API key was removed
+    RevokeToken,
+    /// An OAuth client was registered
+    CreateClient,
+    /// The profile's metadata was changed
+    Metadata,
+    /// The profile was deleted
+    Delete,
+}
+
+/// An entry in the audit log, entries are never changed or removed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub id: String,
+    /// The username of the profile which made the change, empty for changes made
+    /// outside of the API
+    pub actor: String,
+    /// The username of the profile which was changed
+    pub target: String,
+    pub action: AuditAction,
+    /// The target's skills before the change, if the change could have affected them
+    pub before: Option<SkillSet>,
+    /// The target's skills after the change
+    pub after: Option<SkillSet>,
+    pub timestamp: u128,
+    /// The IP address the change was requested from, empty for changes made outside
+    /// of the API
+    pub ip: String,
+    /// Why the change was made, if the actor gave a reason
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl AuditEntry {
+    /// Create a new [`AuditEntry`] for a change made now
+    ///
+    /// # Arguments:
+    /// * `actor` - `String` of the acting profile's username
+    /// * `target` - `String` of the changed profile's username
+    /// * `action` - [`AuditAction`] of the change
+    pub fn new(actor: &str, target: &str, action: AuditAction) -> Self {
+        Self {
+            id: dorsal::utility::uuid(),
+            actor: actor.to_string(),
+            target: target.to_string(),
+            action,
+            before: None,
+            after: None,
+            timestamp: dorsal::utility::unix_epoch_timestamp(),
+            ip: String::new(),
+            reason: None,
+        }
+    }
+
+    /// Record the target's skills before and after the change
+    pub fn skills(mut self, before: &SkillSet, after: &SkillSet) -> Self {
+        self.before = Some(before.clone());
+        self.after = Some(after.clone());
+        self
+    }
+
+    /// Record the IP address the change was requested from
+    pub fn ip(mut self, ip: &str) -> Self {
+        self.ip = ip.to_string();
+        self
+    }
+
+    /// Record why the change was made
+    pub fn reason(mut self, reason: Option<String>) -> Self {
+        self.reason = reason;
+        self
+    }
+}
+
+/// Who is making a change, recorded by the store in the [`AuditEntry`] it writes along
+/// with the change
+#[derive(Clone, Debug, Default)]
+pub struct Actor {
+    /// The username of the acting profile, empty for changes made outside of the API
+    pub username: String,
+    /// The IP address the change was requested from, empty for changes made outside
+    /// of the API
+    pub ip: String,
+    /// Why the change is being made
+    pub reason: Option<String>,
+}
+
+impl Actor {
+    /// Create a new [`Actor`] for a profile
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the acting profile's username
+    pub fn new(username: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            ..Default::default()
         }
     }
+
+    /// An [`Actor`] for changes made outside of the API, such as by `starstraw-admin`
+    /// or skill expiry
+    pub fn system() -> Self {
+        Self::default()
+    }
+
+    /// Set the IP address the change was requested from
+    pub fn ip(mut self, ip: &str) -> Self {
+        self.ip = ip.to_string();
+        self
+    }
+
+    /// Set why the change is being made
+    pub fn reason(mut self, reason: Option<String>) -> Self {
+        self.reason = reason;
+        self
+    }
+
+    /// Create the [`AuditEntry`] of a change made by this actor
+    pub fn entry(&self, target: &str, action: AuditAction) -> AuditEntry {
+        AuditEntry::new(&self.username, target, action)
+            .ip(&self.ip)
+            .reason(self.reason.clone())
+    }
 }
 
+/// Filters for the audit log, every given filter must match
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct ProfileMetadata {
-    /// A secondary token that can be used to authenticate as the account
+pub struct AuditQuery {
     #[serde(default)]
-    pub secondary_token: String,
+    pub actor: Option<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub action: Option<AuditAction>,
+    /// The maximum number of entries to return (at most 500)
+    #[serde(default = "default_audit_limit")]
+    pub limit: usize,
+    /// The number of matching entries to skip
+    #[serde(default)]
+    pub offset: usize,
+    /// Only match entries older than the entry with this `(timestamp, id)`, so pages
+    /// can be read without entries added in between shifting them
+    #[serde(skip)]
+    pub before: Option<(u128, String)>,
 }
 
-impl Default for ProfileMetadata {
+fn default_audit_limit() -> usize {
+    50
+}
+
+impl Default for AuditQuery {
     fn default() -> Self {
         Self {
-            secondary_token: String::new(),
+            actor: None,
+            target: None,
+            action: None,
+            limit: default_audit_limit(),
+            offset: 0,
+            before: None,
         }
     }
 }
 
+impl AuditQuery {
+    /// Check if the given [`AuditEntry`] matches every filter
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor
+            .as_ref()
+            .map(|a| a == &entry.actor)
+            .unwrap_or(true)
+            && self
+                .target
+                .as_ref()
+                .map(|t| t == &entry.target)
+                .unwrap_or(true)
+            && self
+                .action
+                .as_ref()
+                .map(|a| a == &entry.action)
+                .unwrap_or(true)
+            && self
+                .before
+                .as_ref()
+                .map(|(timestamp, id)| (entry.timestamp, &entry.id) < (*timestamp, id))
+                .unwrap_or(true)
+    }
+}
+
 // props
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ProfileCreate {
@@ -155,27 +608,66 @@ pub struct ProfileLogin {
     pub id: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SecondaryTokenCreate {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OAuthClientCreate {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    /// If the client should be given a secret
+    #[serde(default)]
+    pub confidential: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiKeyCreate {
+    pub label: String,
+    #[serde(default)]
+    pub scope: ApiKeyScope,
+    /// How long the key is valid for (in milliseconds), never expires if not given
+    #[serde(default)]
+    pub expires_in: Option<u128>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GrantSkill {
     pub skill: Skill,
+    /// When the skill is removed again (as a unix timestamp in milliseconds), never if not given
+    #[serde(default)]
+    pub expires_at: Option<u128>,
+    #[serde(default)]
+    pub reason: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RevokeSkill {
     pub skill: SkillName,
+    #[serde(default)]
+    pub reason: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GrantTitle {
     pub title: SkillName,
+    /// When the previous title is restored (as a unix timestamp in milliseconds), never if not given
+    #[serde(default)]
+    pub expires_at: Option<u128>,
+    #[serde(default)]
+    pub reason: Option<String>,
 }
 
 /// General API errors
+#[derive(Debug)]
 pub enum StrawError {
     MustBeUnique,
     NotAllowed,
     ValueError,
     NotFound,
+    /// The resource was changed since it was read
+    Conflict,
     Other,
 }
 
@@ -187,6 +679,7 @@ impl StrawError {
             NotAllowed => String::from("You are not allowed to access this resource."),
             ValueError => String::from("One of the field values given is invalid."),
             NotFound => String::from("No asset with this ID could be found."),
+            Conflict => String::from("The resource was changed by another request."),
             _ => String::from("An unspecified error has occured"),
         }
     }
@@ -214,6 +707,15 @@ impl IntoResponse for StrawError {
                 }),
             )
                 .into_response(),
+            Conflict => (
+                StatusCode::CONFLICT,
+                Json(DefaultReturn::<u16> {
+                    success: false,
+                    message: self.to_string(),
+                    payload: 409,
+                }),
+            )
+                .into_response(),
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(DefaultReturn::<u16> {
@@ -227,10 +729,75 @@ impl IntoResponse for StrawError {
     }
 }
 
+/// OAuth errors ([RFC 6749](https://www.rfc-editor.org/rfc/rfc6749#section-5.2))
+#[derive(Debug)]
+pub enum OAuthError {
+    InvalidRequest,
+    InvalidClient,
+    InvalidGrant,
+    InvalidScope,
+    InvalidToken,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    AccessDenied,
+    LoginRequired,
+    ServerError,
+}
+
+impl OAuthError {
+    /// Get the `error` code of the error
+    pub fn code(&self) -> &'static str {
+        use OAuthError::*;
+        match self {
+            InvalidRequest => "invalid_request",
+            InvalidClient => "invalid_client",
+            InvalidGrant => "invalid_grant",
+            InvalidScope => "invalid_scope",
+            InvalidToken => "invalid_token",
+            UnsupportedGrantType => "unsupported_grant_type",
+            UnsupportedResponseType => "unsupported_response_type",
+            AccessDenied => "access_denied",
+            LoginRequired => "login_required",
+            ServerError => "server_error",
+        }
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        use crate::model::OAuthError::*;
+        let status = match self {
+            InvalidClient | InvalidToken => StatusCode::UNAUTHORIZED,
+            ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+
+        let mut res = (status, Json(serde_json::json!({ "error": self.code() }))).into_response();
+
+        if let InvalidToken = self {
+            res.headers_mut().insert(
+                "WWW-Authenticate",
+                "Bearer error=\"invalid_token\"".parse().unwrap(),
+            );
+        }
+
+        res
+    }
+}
+
 // ...
-/// Simple manager for profile skills
+/// Simple manager for profile skills and their [`SkillGrant`]s
 #[derive(Clone)]
-pub struct SkillManager(pub SkillSet);
+pub struct SkillManager {
+    skills: SkillSet,
+    grants: Vec<SkillGrant>,
+}
+
+impl From<&Profile> for SkillManager {
+    fn from(profile: &Profile) -> Self {
+        Self::with_grants(profile.skills.clone(), profile.grants.clone())
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ProfileStats {
@@ -239,6 +806,10 @@ pub struct ProfileStats {
     pub title: SkillName,
     pub abilities: HashMap<SkillName, f32>,
     pub skills: SkillSet,
+    /// The [`SkillGrant`]s of `skills`, only included for privileged viewers
+    /// ([`SkillManager::get_stats_with_grants`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub grants: Vec<SkillGrant>,
 }
 
 impl Default for ProfileStats {
@@ -249,14 +820,41 @@ impl Default for ProfileStats {
             title: SkillName::Normal,
             abilities: HashMap::new(),
             skills: [SkillName::Normal.into()].to_vec(),
+            grants: Vec::new(),
         }
     }
 }
 
 impl SkillManager {
-    /// Get profile statistics based on its skills
+    /// Create a new [`SkillManager`] for the given skills, without any [`SkillGrant`]s
+    pub fn new(skills: SkillSet) -> Self {
+        Self::with_grants(skills, Vec::new())
+    }
+
+    /// Create a new [`SkillManager`] for the given skills and their [`SkillGrant`]s
+    pub fn with_grants(skills: SkillSet, grants: Vec<SkillGrant>) -> Self {
+        Self { skills, grants }
+    }
+
+    /// Get the managed skills
+    pub fn skills(&self) -> &SkillSet {
+        &self.skills
+    }
+
+    /// Get the [`SkillGrant`]s of the managed skills
+    pub fn grants(&self) -> &[SkillGrant] {
+        &self.grants
+    }
+
+    /// Get the managed skills and their [`SkillGrant`]s
+    pub fn into_parts(self) -> (SkillSet, Vec<SkillGrant>) {
+        (self.skills, self.grants)
+    }
+
+    /// Get profile statistics based on its skills, expired skills are ignored
     pub fn get_stats(&self) -> ProfileStats {
-        let mut iter = self.0.iter();
+        let skills = self.active();
+        let mut iter = skills.iter();
 
         // resolve title
         // our title is the first title present
@@ -295,51 +893,201 @@ impl SkillManager {
             defense,
             title: title.0 .1,
             abilities,
-            skills: self.0.clone(),
+            skills,
+            grants: Vec::new(),
         }
     }
 
+    /// Get profile statistics ([`SkillManager::get_stats`]) along with the
+    /// [`SkillGrant`]s of its skills
+    pub fn get_stats_with_grants(&self) -> ProfileStats {
+        let mut stats = self.get_stats();
+        stats.grants = self
+            .grants
+            .iter()
+            .filter(|g| stats.skills.iter().any(|s| s.0 .1 == g.name))
+            .cloned()
+            .collect();
+
+        stats
+    }
+
+    /// Get every skill which hasn't expired
+    pub fn active(&self) -> SkillSet {
+        let now = dorsal::utility::unix_epoch_timestamp();
+
+        self.skills
+            .iter()
+            .filter(|s| {
+                !self
+                    .grants
+                    .iter()
+                    .any(|g| (g.name == s.0 .1) && g.expired(now))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Remove every expired skill (and its [`SkillGrant`]). Returns `true` if anything
+    /// was removed
+    ///
+    /// Temporary titles are held in front of the title they replaced, so that title is
+    /// used again. A profile left without any title is given `Normal`.
+    pub fn expire(&mut self) -> bool {
+        let now = dorsal::utility::unix_epoch_timestamp();
+        let expired: Vec<SkillName> = self
+            .grants
+            .iter()
+            .filter(|g| g.expired(now))
+            .map(|g| g.name.clone())
+            .collect();
+
+        if expired.is_empty() {
+            return false;
+        }
+
+        self.skills.retain(|s| !expired.contains(&s.0 .1));
+        self.grants.retain(|g| !expired.contains(&g.name));
+
+        self.restore_title(&GrantContext::default());
+        true
+    }
+
+    /// Give the profile the `Normal` title if it doesn't have a title
+    fn restore_title(&mut self, context: &GrantContext) {
+        if self.skills.iter().any(|s| s.0 .0 == SkillType::Title) {
+            return;
+        }
+
+        let title: Skill = SkillName::Normal.into();
+        self.grant(
+            &title.0 .1,
+            &GrantContext {
+                expires_at: None,
+                ..context.clone()
+            },
+        );
+        self.skills.insert(0, title);
+    }
+
+    /// Replace the [`SkillGrant`] of the given skill
+    fn grant(&mut self, name: &SkillName, context: &GrantContext) {
+        self.grants.retain(|g| &g.name != name);
+        self.grants.push(SkillGrant::new(name.clone(), context));
+    }
+
     /// Update the profile title
-    pub fn title(&mut self, skill: Skill) -> Result<()> {
+    ///
+    /// The title must be registered as a `Title` in the given [`SkillRegistry`] and meet
+    /// its [`SkillRules`](crate::registry::SkillRules). The caller must still check that
+    /// whoever sets it is allowed to ([`SkillManager::delegate`]).
+    ///
+    /// A title granted with an `expires_at` is held in front of the current title
+    /// (replacing any other temporary title), which is used again once it expires. Setting
+    /// a title without one ends any temporary title.
+    pub fn title(
+        &mut self,
+        registry: &SkillRegistry,
+        skill: Skill,
+        context: &GrantContext,
+    ) -> Result<()> {
+        if skill.0 .0 != SkillType::Title {
+            return Err(StrawError::ValueError);
+        }
+
+        // make sure title is valid, the same as any pushed skill
+        registry.check(&skill, &self.get_stats())?;
+        context.check()?;
+
+        // end any temporary title
+        let temporary: Vec<SkillName> = self
+            .grants
+            .iter()
+            .filter(|g| g.expires_at.is_some())
+            .map(|g| g.name.clone())
+            .collect();
+
+        for name in temporary {
+            if self
+                .skills
+                .iter()
+                .any(|s| (s.0 .0 == SkillType::Title) && (s.0 .1 == name))
+            {
+                self.skills
+                    .retain(|s| !((s.0 .0 == SkillType::Title) && (s.0 .1 == name)));
+                self.grants.retain(|g| g.name != name);
+            }
+        }
+
+        if context.expires_at.is_some() {
+            // grants are tracked by name, so the current title can't be held again
+            if self.has_skill(skill.0 .1.clone()) {
+                return Err(StrawError::ValueError);
+            }
+
+            self.grant(&skill.0 .1, context);
+            self.skills.insert(0, skill);
+            return Ok(());
+        }
+
         // find current title location
-        for (i, skill) in self.0.clone().iter().enumerate() {
-            if skill.0 .0 != SkillType::Title {
+        for (i, existing) in self.skills.clone().iter().enumerate() {
+            if existing.0 .0 != SkillType::Title {
                 continue;
             }
 
-            let _ = std::mem::replace(&mut self.0[i], skill.to_owned());
+            self.grants.retain(|g| g.name != existing.0 .1);
+            self.grant(&skill.0 .1, context);
+            let _ = std::mem::replace(&mut self.skills[i], skill);
             return Ok(());
         }
 
         // since we didn't return earlier, we didn't previously have a title skill
         // this means we can just insert the skill at 0
-        self.0.insert(0, skill);
+        self.grant(&skill.0 .1, context);
+        self.skills.insert(0, skill);
         Ok(())
     }
 
     /// Remove the given skill by name
-    pub fn remove(&mut self, name: SkillName) -> Result<()> {
-        for (i, skill) in self.0.clone().iter().enumerate() {
-            if skill.0 .1 != name {
-                continue;
-            }
-
-            self.0.remove(i);
-        }
+    ///
+    /// A profile left without any title is given `Normal`, granted by the given context.
+    pub fn remove(&mut self, name: SkillName, context: &GrantContext) -> Result<()> {
+        context.check()?;
+        self.grants.retain(|g| g.name != name);
+        self.skills.retain(|s| s.0 .1 != name);
 
+        self.restore_title(context);
         Ok(())
     }
 
-    /// Push the given skill
-    pub fn push(&mut self, skill: Skill) -> Result<()> {
+    /// Push the given skill, which must be registered in the given [`SkillRegistry`]
+    ///
+    /// Titles can't be pushed, they're set with [`SkillManager::title`].
+    pub fn push(
+        &mut self,
+        registry: &SkillRegistry,
+        skill: Skill,
+        context: &GrantContext,
+    ) -> Result<()> {
+        // a pushed title could take over once the current title is revoked
+        if skill.0 .0 == SkillType::Title {
+            return Err(StrawError::ValueError);
+        }
+
         // make sure skill is valid
         // this makes sure we aren't granted any skills we shouldn't be able to have
-        if !skill.0 .1.is_valid(self.get_stats()) {
+        registry.check(&skill, &self.get_stats())?;
+        context.check()?;
+
+        // grants are tracked by name, so a held skill can't be made temporary
+        if context.expires_at.is_some() && self.has_skill(skill.0 .1.clone()) {
             return Err(StrawError::ValueError);
         }
 
         // ...
-        self.0.push(skill);
+        self.grant(&skill.0 .1, context);
+        self.skills.push(skill);
         Ok(())
     }
 
@@ -351,8 +1099,269 @@ impl SkillManager {
         ((me.power > them.defense) && (them.power <= me.power)) | (me.title == SkillName::God)
     }
 
+    /// Check if the profile is allowed to change the skills of another [`SkillManager`]
+    /// into `after`
+    ///
+    /// The profile must be able to [`act`](Self::act) on the other profile, and the
+    /// changed profile can't end up with more power, defense, or abilities than the
+    /// profile itself, or with another title. The `God` title is never limited.
+    ///
+    /// # Arguments:
+    /// * `other` - the profile before the change
+    /// * `after` - the profile after the change
+    pub fn delegate(&self, other: SkillManager, after: &SkillManager) -> bool {
+        let me = self.get_stats();
+
+        if me.title == SkillName::God {
+            return true;
+        }
+
+        let before = other.get_stats();
+
+        if !self.act(other) {
+            return false;
+        }
+
+        // abilities the profile already had are kept, new ones must be held by us
+        let them = after.get_stats();
+        (them.title == before.title)
+            && (them.power <= me.power)
+            && (them.defense <= me.defense)
+            && them.abilities.iter().all(|(name, value)| {
+                me.abilities.get(name).is_some_and(|v| value <= v)
+                    | before.abilities.get(name).is_some_and(|v| value <= v)
+            })
+    }
+
     /// Check if the [`SkillManager`] contains the requested [`SkillName`]
     pub fn has_skill(&self, skill: SkillName) -> bool {
-        self.0.iter().find(|s| s.0 .1 == skill).is_some()
+        self.skills.iter().find(|s| s.0 .1 == skill).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(skills: &[SkillName]) -> SkillManager {
+        SkillManager::new(skills.iter().cloned().map(Into::into).collect())
+    }
+
+    fn names(manager: &SkillManager) -> Vec<SkillName> {
+        manager.skills().iter().map(|s| s.0 .1.clone()).collect()
+    }
+
+    #[test]
+    fn push_rejects_titles() {
+        let registry = SkillRegistry::builtin();
+        let mut target = manager(&[SkillName::Normal]);
+
+        assert!(matches!(
+            target.push(
+                &registry,
+                SkillName::Administrator.into(),
+                &GrantContext::default()
+            ),
+            Err(StrawError::ValueError)
+        ));
+
+        target
+            .push(
+                &registry,
+                SkillName::Trustworthy.into(),
+                &GrantContext::default(),
+            )
+            .unwrap();
+        assert_eq!(names(&target), [SkillName::Normal, SkillName::Trustworthy]);
+    }
+
+    #[test]
+    fn title_checks_rules() {
+        let registry = SkillRegistry::builtin();
+        let mut target = manager(&[SkillName::Normal, SkillName::Trustworthy]);
+
+        // God isn't grantable, and only titles can be set as one
+        for skill in [SkillName::God, SkillName::Trustworthy] {
+            assert!(matches!(
+                target.title(&registry, skill.into(), &GrantContext::default()),
+                Err(StrawError::ValueError)
+            ));
+        }
+
+        target
+            .title(
+                &registry,
+                SkillName::Manager.into(),
+                &GrantContext::by("granter"),
+            )
+            .unwrap();
+
+        assert_eq!(names(&target), [SkillName::Manager, SkillName::Trustworthy]);
+        assert_eq!(target.grants()[0].name, SkillName::Manager);
+        assert_eq!(target.grants()[0].granted_by, "granter");
+    }
+
+    #[test]
+    fn temporary_title_falls_back() {
+        let registry = SkillRegistry::builtin();
+        let mut target = manager(&[SkillName::Normal]);
+
+        let expires_at = dorsal::utility::unix_epoch_timestamp() + 60_000;
+        target
+            .title(
+                &registry,
+                SkillName::Manager.into(),
+                &GrantContext::default().expires_at(Some(expires_at)),
+            )
+            .unwrap();
+
+        assert_eq!(target.get_stats().title, SkillName::Manager);
+        assert_eq!(names(&target), [SkillName::Manager, SkillName::Normal]);
+
+        // once it has expired, the old title is used again
+        let (skills, mut grants) = target.into_parts();
+        grants[0].expires_at = Some(1);
+        let mut target = SkillManager::with_grants(skills, grants);
+
+        assert_eq!(target.get_stats().title, SkillName::Normal);
+        assert!(target.expire());
+        assert!(!target.expire());
+        assert_eq!(names(&target), [SkillName::Normal]);
+        assert!(target.grants().is_empty());
+    }
+
+    #[test]
+    fn expire_restores_normal() {
+        let skills: SkillSet = vec![
+            SkillName::Manager.into(),
+            SkillName::Trustworthy.into(),
+            SkillName::Protected.into(),
+        ];
+
+        let expired = GrantContext::default().expires_at(Some(1));
+        let mut target = SkillManager::with_grants(
+            skills,
+            vec![
+                SkillGrant::new(SkillName::Manager, &expired),
+                SkillGrant::new(SkillName::Trustworthy, &expired),
+                SkillGrant::new(SkillName::Protected, &GrantContext::default()),
+            ],
+        );
+
+        assert!(target.expire());
+        assert_eq!(names(&target), [SkillName::Normal, SkillName::Protected]);
+    }
+
+    #[test]
+    fn remove_skill_held_twice() {
+        let registry = SkillRegistry::builtin();
+        let mut target = manager(&[SkillName::Normal]);
+
+        for _ in 0..2 {
+            target
+                .push(
+                    &registry,
+                    SkillName::Trustworthy.into(),
+                    &GrantContext::default(),
+                )
+                .unwrap();
+        }
+
+        target
+            .remove(SkillName::Trustworthy, &GrantContext::default())
+            .unwrap();
+        assert_eq!(names(&target), [SkillName::Normal]);
+
+        // a profile left without a title is given Normal
+        let mut target = manager(&[SkillName::Manager, SkillName::Protected]);
+        target
+            .remove(SkillName::Manager, &GrantContext::by("granter"))
+            .unwrap();
+        assert_eq!(names(&target), [SkillName::Normal, SkillName::Protected]);
+    }
+
+    #[test]
+    fn delegate_limits() {
+        let registry = SkillRegistry::builtin();
+        let actor = manager(&[SkillName::Manager]);
+        let target = manager(&[SkillName::Normal]);
+
+        // a weaker skill is fine
+        let mut after = target.clone();
+        after
+            .push(
+                &registry,
+                SkillName::Trustworthy.into(),
+                &GrantContext::default(),
+            )
+            .unwrap();
+        assert!(actor.delegate(target.clone(), &after));
+
+        // an ability we don't hold isn't
+        let mut after = target.clone();
+        after
+            .push(
+                &registry,
+                SkillName::Vanish.into(),
+                &GrantContext::default(),
+            )
+            .unwrap();
+        assert!(!actor.delegate(target.clone(), &after));
+
+        // neither is a stronger profile
+        let mut after = target.clone();
+        for _ in 0..10 {
+            after
+                .push(
+                    &registry,
+                    SkillName::Master.into(),
+                    &GrantContext::default(),
+                )
+                .unwrap();
+        }
+        assert!(!actor.delegate(target.clone(), &after));
+
+        // or changing the title, even to a weaker one
+        let mut after = manager(&[SkillName::Manager]);
+        after
+            .title(
+                &registry,
+                SkillName::Normal.into(),
+                &GrantContext::default(),
+            )
+            .unwrap();
+        assert!(
+            !manager(&[SkillName::Administrator]).delegate(manager(&[SkillName::Manager]), &after)
+        );
+
+        // a title pushed behind the current one can't take over once it's revoked
+        let mut after = target.clone();
+        after.skills.push(SkillName::Manager.into());
+        after
+            .remove(SkillName::Normal, &GrantContext::default())
+            .unwrap();
+        assert_eq!(after.get_stats().title, SkillName::Manager);
+        assert!(!manager(&[SkillName::Administrator]).delegate(target.clone(), &after));
+
+        // a profile we can't act on can't be changed at all
+        let stronger = manager(&[SkillName::Administrator]);
+        assert!(!actor.delegate(stronger.clone(), &stronger));
+
+        // God can do anything
+        let god = manager(&[SkillName::God]);
+        assert!(god.delegate(target.clone(), &manager(&[SkillName::Administrator])));
+    }
+
+    #[test]
+    fn api_key_scope_caps_power() {
+        let skills: SkillSet = vec![SkillName::God.into(), SkillName::Master.into()];
+        let scope = ApiKeyScope {
+            max_power: Some(500.0),
+            ..ApiKeyScope::default()
+        };
+
+        let restricted = SkillManager::new(scope.restrict(skills)).get_stats();
+        assert_ne!(restricted.title, SkillName::God);
+        assert!(restricted.power <= 500.0);
     }
 }
@@ -0,0 +1,739 @@
+//! OAuth 2.0 (authorization code with PKCE) and OpenID Connect provider
+use crate::database::{Database, OAUTH_TOKEN_LIFETIME};
+use crate::extract::{bearer_token, AuthProfile, ClientInfo, OptionalAuthProfile};
+use crate::jwt::{self, Claims};
+use crate::model::{
+    Actor, OAuthClient, OAuthClientCreate, OAuthCode, OAuthError, Profile, ProfileStats,
+    SigningKey, SkillManager, SkillName, StrawError,
+};
+use dorsal::{utility, DefaultReturn};
+
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::{Form, Json};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Scopes which can be requested by clients
+pub const SCOPES: [&str; 2] = ["openid", "profile"];
+/// How long a consent screen can be answered for after it was shown (in milliseconds)
+pub const CONSENT_LIFETIME: u128 = 10 * 60 * 1000;
+
+/// Build a redirect to the given URI with the given (non-empty) query parameters appended
+fn redirect_with(uri: &str, params: &[(&str, &str)]) -> Response {
+    let params: Vec<&(&str, &str)> = params.iter().filter(|p| !p.1.is_empty()).collect();
+    let query = serde_urlencoded::to_string(params).unwrap();
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    Redirect::to(&format!("{uri}{separator}{query}")).into_response()
+}
+
+/// Authenticate an [`OAuthClient`] with `client_secret_basic`, falling back to the
+/// given `client_secret_post` credentials
+///
+/// Public clients are authenticated by their `client_id` alone.
+async fn authenticate_client(
+    database: &Database,
+    headers: &HeaderMap,
+    client_id: String,
+    client_secret: String,
+) -> Result<OAuthClient, OAuthError> {
+    // get client credentials
+    let (client_id, client_secret) = match headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "))
+    {
+        Some(basic) => {
+            let decoded = match STANDARD
+                .decode(basic.trim())
+                .ok()
+                .and_then(|d| String::from_utf8(d).ok())
+            {
+                Some(d) => d,
+                None => return Err(OAuthError::InvalidClient),
+            };
+
+            match decoded.split_once(':') {
+                Some((id, secret)) => (id.to_string(), secret.to_string()),
+                None => return Err(OAuthError::InvalidClient),
+            }
+        }
+        None => (client_id, client_secret),
+    };
+
+    // check client
+    let client = match database.get_oauth_client(client_id).await {
+        Ok(c) => c,
+        Err(_) => return Err(OAuthError::InvalidClient),
+    };
+
+    if !client.secret.is_empty() && (utility::hash(client_secret) != client.secret) {
+        return Err(OAuthError::InvalidClient);
+    }
+
+    Ok(client)
+}
+
+/// Register a new [`OAuthClient`] (requires the "God" title)
+///
+/// The unhashed client secret is returned as the message, and cannot be viewed again.
+pub async fn create_client_request(
+    auth: AuthProfile,
+    client: ClientInfo,
+    State(database): State<Database>,
+    Json(props): Json<OAuthClientCreate>,
+) -> impl IntoResponse {
+    // check permission
+    if auth.stats.title != SkillName::God {
+        return Json(DefaultReturn {
+            success: false,
+            message: StrawError::NotAllowed.to_string(),
+            payload: None,
+        });
+    }
+
+    // create client
+    match database
+        .create_oauth_client(
+            auth.profile.id,
+            props.name,
+            props.redirect_uris,
+            props.confidential,
+            &Actor::new(&auth.profile.username).ip(&client.ip),
+        )
+        .await
+    {
+        Ok((client, secret)) => Json(DefaultReturn {
+            success: true,
+            message: secret,
+            payload: Some(client),
+        }),
+        Err(e) => Json(DefaultReturn {
+            success: false,
+            message: e.to_string(),
+            payload: None,
+        }),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuthorizeQueryProps {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub scope: String,
+    #[serde(default)]
+    pub state: String,
+    #[serde(default)]
+    pub code_challenge: String,
+    #[serde(default)]
+    pub code_challenge_method: String,
+    #[serde(default)]
+    pub nonce: String,
+}
+
+/// Escape a value for use in HTML
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The HMAC key of consent decisions, the private half of a [`SigningKey`]
+fn consent_key(key: &SigningKey) -> ring::hmac::Key {
+    ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key.private.as_bytes())
+}
+
+/// The message signed for a consent decision
+fn consent_message(profile: &Profile, props: &AuthorizeQueryProps, timestamp: u128) -> String {
+    format!(
+        "consent:{}:{}:{}:{}:{timestamp}",
+        profile.id, props.client_id, props.redirect_uri, props.scope
+    )
+}
+
+/// Get the token which must be sent along with a consent decision, so the decision
+/// can only come from a consent screen shown to the profile itself
+///
+/// The token is signed with the current signing key and can be used for
+/// [`CONSENT_LIFETIME`]. Host apps showing their own consent screen should include it
+/// as the `consent` field of the form they post to the authorization endpoint.
+///
+/// # Arguments:
+/// * `database` - the [`Database`] holding the signing keys
+/// * `profile` - the [`Profile`] giving consent
+/// * `props` - the authorization request
+pub async fn consent_token(
+    database: &Database,
+    profile: &Profile,
+    props: &AuthorizeQueryProps,
+) -> Result<String, StrawError> {
+    let mut keys = database.get_signing_keys().await?;
+
+    if keys.is_empty() {
+        keys.push(database.create_signing_key().await?);
+    }
+
+    let timestamp = utility::unix_epoch_timestamp();
+    let tag = ring::hmac::sign(
+        &consent_key(&keys[0]),
+        consent_message(profile, props, timestamp).as_bytes(),
+    );
+    Ok(format!(
+        "{}.{timestamp}.{}",
+        keys[0].id,
+        URL_SAFE_NO_PAD.encode(tag.as_ref())
+    ))
+}
+
+/// Check a token made by [`consent_token`] for the same profile and request, which
+/// must not be older than [`CONSENT_LIFETIME`]
+pub async fn check_consent_token(
+    database: &Database,
+    profile: &Profile,
+    props: &AuthorizeQueryProps,
+    token: &str,
+) -> bool {
+    let mut parts = token.splitn(3, '.');
+    let (kid, timestamp, tag) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(k), Some(t), Some(s)) => (k, t, s),
+        _ => return false,
+    };
+
+    let timestamp: u128 = match timestamp.parse() {
+        Ok(t) => t,
+        Err(_) => return false,
+    };
+
+    let now = utility::unix_epoch_timestamp();
+    if (timestamp > now) | (now.saturating_sub(timestamp) > CONSENT_LIFETIME) {
+        return false;
+    }
+
+    let tag = match URL_SAFE_NO_PAD.decode(tag) {
+        Ok(t) => t,
+        Err(_) => return false,
+    };
+
+    // find key (any published key, so a rotation doesn't break open consent screens)
+    let keys = database.get_signing_keys().await.unwrap_or_default();
+    let key = match keys.iter().find(|k| k.id == kid) {
+        Some(k) => k,
+        None => return false,
+    };
+
+    ring::hmac::verify(
+        &consent_key(key),
+        consent_message(profile, props, timestamp).as_bytes(),
+        &tag,
+    )
+    .is_ok()
+}
+
+/// Check an authorization request, returning its client and the profile making it
+///
+/// The error is paired with whether it can be redirected back to the client.
+async fn check_authorize(
+    database: &Database,
+    auth: OptionalAuthProfile,
+    props: &AuthorizeQueryProps,
+) -> std::result::Result<(OAuthClient, AuthProfile), (OAuthError, bool)> {
+    // check client and redirect uri (we can't redirect errors back if these are wrong)
+    let client = match database.get_oauth_client(props.client_id.clone()).await {
+        Ok(c) => c,
+        Err(_) => return Err((OAuthError::InvalidClient, false)),
+    };
+
+    if !client.redirect_uris.contains(&props.redirect_uri) {
+        return Err((OAuthError::InvalidRequest, false));
+    }
+
+    // check request
+    if props.response_type != "code" {
+        return Err((OAuthError::UnsupportedResponseType, true));
+    }
+
+    if !props.code_challenge_method.is_empty() && (props.code_challenge_method != "S256") {
+        // only S256 is supported
+        return Err((OAuthError::InvalidRequest, true));
+    }
+
+    if client.secret.is_empty() && props.code_challenge.is_empty() {
+        // public clients must use PKCE
+        return Err((OAuthError::InvalidRequest, true));
+    }
+
+    if props.scope.split_whitespace().any(|s| !SCOPES.contains(&s)) {
+        return Err((OAuthError::InvalidScope, true));
+    }
+
+    // check user (api keys can't authorize clients for the full profile)
+    match auth.0 {
+        Some(a) if a.api_key.is_none() => Ok((client, a)),
+        _ => Err((OAuthError::LoginRequired, true)),
+    }
+}
+
+/// Respond to a failed [`check_authorize`]
+fn authorize_error(props: &AuthorizeQueryProps, error: OAuthError, redirect: bool) -> Response {
+    if !redirect {
+        return error.into_response();
+    }
+
+    redirect_with(
+        &props.redirect_uri,
+        &[("error", error.code()), ("state", &props.state)],
+    )
+}
+
+/// Authorization endpoint
+///
+/// Shows a consent screen for the profile making the request, which posts the
+/// decision back to [`consent_request`].
+pub async fn authorize_request(
+    auth: OptionalAuthProfile,
+    State(database): State<Database>,
+    Query(props): Query<AuthorizeQueryProps>,
+) -> Response {
+    let (client, auth) = match check_authorize(&database, auth, &props).await {
+        Ok(r) => r,
+        Err((e, redirect)) => return authorize_error(&props, e, redirect),
+    };
+
+    let consent = match consent_token(&database, &auth.profile, &props).await {
+        Ok(c) => c,
+        Err(_) => return authorize_error(&props, OAuthError::ServerError, true),
+    };
+
+    // return
+    let scope = if props.scope.trim().is_empty() {
+        "your profile".to_string()
+    } else {
+        props
+            .scope
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(", ")
+    };
+
+    (
+        [
+            ("Content-Type", "text/html"),
+            ("Cache-Control", "no-store"),
+            // the screen can't be framed to trick the profile into allowing the client
+            ("X-Frame-Options", "DENY"),
+            ("Content-Security-Policy", "frame-ancestors 'none'"),
+        ],
+        format!(
+            "<!DOCTYPE html>
+        <head>
+            <title>Authorize {name}</title>
+        </head>
+        <body>
+            <p><b>{name}</b> wants to access {scope} as <b>{username}</b>.</p>
+            <form method=\"post\">
+                <input type=\"hidden\" name=\"consent\" value=\"{consent}\" />
+                <button type=\"submit\" name=\"decision\" value=\"allow\">Allow</button>
+                <button type=\"submit\" name=\"decision\" value=\"deny\">Deny</button>
+            </form>
+        </body>",
+            name = escape(&client.name),
+            scope = escape(&scope),
+            username = escape(&auth.profile.username),
+            consent = escape(&consent),
+        ),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ConsentFormProps {
+    /// The [`consent_token`] of the request
+    pub consent: String,
+    /// `"allow"` or `"deny"`
+    pub decision: String,
+}
+
+/// Authorization endpoint (consent decision)
+///
+/// Redirects back to the client with an authorization code if the profile allowed
+/// the client, or with `access_denied` otherwise.
+pub async fn consent_request(
+    auth: OptionalAuthProfile,
+    State(database): State<Database>,
+    Query(props): Query<AuthorizeQueryProps>,
+    Form(form): Form<ConsentFormProps>,
+) -> Response {
+    let (client, auth) = match check_authorize(&database, auth, &props).await {
+        Ok(r) => r,
+        Err((e, redirect)) => return authorize_error(&props, e, redirect),
+    };
+
+    // check decision
+    if !check_consent_token(&database, &auth.profile, &props, &form.consent).await {
+        return authorize_error(&props, OAuthError::InvalidRequest, true);
+    }
+
+    if form.decision != "allow" {
+        return authorize_error(&props, OAuthError::AccessDenied, true);
+    }
+
+    // create code
+    let code = match database
+        .create_oauth_code(OAuthCode {
+            code: String::new(),
+            client: client.id,
+            profile: auth.profile.id,
+            redirect_uri: props.redirect_uri.clone(),
+            scope: props.scope.clone(),
+            challenge: props.code_challenge.clone(),
+            nonce: props.nonce.clone(),
+            created: 0,
+        })
+        .await
+    {
+        Ok(c) => c,
+        Err(_) => return authorize_error(&props, OAuthError::ServerError, true),
+    };
+
+    // return
+    redirect_with(
+        &props.redirect_uri,
+        &[("code", &code), ("state", &props.state)],
+    )
+}
+
+#[derive(Deserialize)]
+pub struct TokenFormProps {
+    pub grant_type: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    #[serde(default)]
+    pub code_verifier: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    /// Seconds until the access token expires
+    pub expires_in: u128,
+    pub scope: String,
+    /// A signed [`Claims`] JWT, given if the `openid` scope was granted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// Token endpoint
+///
+/// Clients authenticate with `client_secret_basic`, `client_secret_post`, or only
+/// their `client_id` (public clients, which must have used PKCE).
+pub async fn token_request(
+    headers: HeaderMap,
+    State(database): State<Database>,
+    Form(props): Form<TokenFormProps>,
+) -> Response {
+    if props.grant_type != "authorization_code" {
+        return OAuthError::UnsupportedGrantType.into_response();
+    }
+
+    // check client
+    let client = match authenticate_client(
+        &database,
+        &headers,
+        props.client_id,
+        props.client_secret,
+    )
+    .await
+    {
+        Ok(c) => c,
+        Err(e) => return e.into_response(),
+    };
+
+    // check code (it's only used up once the exchange is known to be valid)
+    let code = match database.get_oauth_code(props.code.clone()).await {
+        Ok(c) => c,
+        Err(_) => return OAuthError::InvalidGrant.into_response(),
+    };
+
+    if (code.client != client.id) | (code.redirect_uri != props.redirect_uri) {
+        return OAuthError::InvalidGrant.into_response();
+    }
+
+    if !code.challenge.is_empty() {
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(props.code_verifier.as_bytes()));
+
+        if props.code_verifier.is_empty() | (challenge != code.challenge) {
+            return OAuthError::InvalidGrant.into_response();
+        }
+    }
+
+    let code = match database.consume_oauth_code(props.code).await {
+        Ok(c) => c,
+        Err(_) => return OAuthError::InvalidGrant.into_response(),
+    };
+
+    // create id token
+    let id_token = if code.scope.split_whitespace().any(|s| s == "openid") {
+        let profile = match database.get_profile_by_hashed(code.profile.clone()).await {
+            Ok(p) => p,
+            Err(_) => return OAuthError::InvalidGrant.into_response(),
+        };
+
+        let mut claims = Claims::new(&database, &profile);
+        claims.aud = client.id.clone();
+        claims.nonce = code.nonce.clone();
+
+        match jwt::sign(&database, &claims).await {
+            Ok(t) => Some(t),
+            Err(_) => return OAuthError::ServerError.into_response(),
+        }
+    } else {
+        None
+    };
+
+    // create token
+    let (token, unhashed) = match database
+        .create_oauth_token(client.id, code.profile, code.scope)
+        .await
+    {
+        Ok(t) => t,
+        Err(_) => return OAuthError::ServerError.into_response(),
+    };
+
+    // return
+    (
+        [("Cache-Control", "no-store")],
+        Json(TokenResponse {
+            access_token: unhashed,
+            token_type: "Bearer".to_string(),
+            expires_in: OAUTH_TOKEN_LIFETIME / 1000,
+            scope: token.scope,
+            id_token,
+        }),
+    )
+        .into_response()
+}
+
+/// Claims about a profile, derived from its [`ProfileStats`](crate::model::ProfileStats)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UserInfo {
+    /// The public ID of the profile ([`Profile::uid`](crate::model::Profile::uid))
+    pub sub: String,
+    pub preferred_username: String,
+    pub title: SkillName,
+    pub power: f32,
+    pub defense: f32,
+    pub abilities: HashMap<SkillName, f32>,
+}
+
+/// UserInfo endpoint
+pub async fn userinfo_request(headers: HeaderMap, State(database): State<Database>) -> Response {
+    let token = match bearer_token(&headers) {
+        Some(t) => t,
+        None => return OAuthError::InvalidToken.into_response(),
+    };
+
+    let token = match database.get_oauth_token_by_unhashed(token).await {
+        Ok(t) => t,
+        Err(_) => return OAuthError::InvalidToken.into_response(),
+    };
+
+    let profile = match database.get_profile_by_hashed(token.profile).await {
+        Ok(p) => p,
+        Err(_) => return OAuthError::InvalidToken.into_response(),
+    };
+
+    // return
+    let stats = SkillManager::from(&profile).get_stats();
+
+    Json(UserInfo {
+        sub: profile.uid,
+        preferred_username: profile.username,
+        title: stats.title,
+        power: stats.power,
+        defense: stats.defense,
+        abilities: stats.abilities,
+    })
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct IntrospectFormProps {
+    pub token: String,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+}
+
+/// Token introspection response ([RFC 7662](https://www.rfc-editor.org/rfc/rfc7662#section-2.2))
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Introspection {
+    pub active: bool,
+    /// The kind of token: `session`, `api_key`, `account`, `secondary`, `access_token` or `jwt`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    /// The public ID of the profile ([`Profile::uid`](crate::model::Profile::uid))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Expires at (seconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ProfileStats>,
+}
+
+impl Introspection {
+    /// Create an active [`Introspection`] for the given [`Profile`]
+    fn active(token_type: &str, profile: Profile) -> Self {
+        let stats = SkillManager::from(&profile).get_stats();
+
+        Self {
+            active: true,
+            token_type: Some(token_type.to_string()),
+            sub: Some(profile.uid),
+            username: Some(profile.username),
+            stats: Some(stats),
+            ..Default::default()
+        }
+    }
+}
+
+/// Resolve any starstraw token into an [`Introspection`]
+pub async fn introspect(database: &Database, token: String) -> Introspection {
+    // session
+    if let Ok(session) = database.get_session_by_unhashed(token.clone()).await {
+        if let Ok(profile) = database.get_profile_by_hashed(session.profile).await {
+            return Introspection::active("session", profile);
+        }
+    }
+
+    // api key
+    if let Ok(k) = database.get_api_key_by_unhashed(token.clone()).await {
+        if let Ok(mut profile) = database.get_profile_by_hashed(k.profile).await {
+            profile.skills = k.scope.restrict(profile.skills);
+            return Introspection {
+                exp: Some((k.expires / 1000) as u64).filter(|e| *e != 0),
+                ..Introspection::active("api_key", profile)
+            };
+        }
+    }
+
+    // oauth access token
+    if let Ok(t) = database.get_oauth_token_by_unhashed(token.clone()).await {
+        if let Ok(profile) = database.get_profile_by_hashed(t.profile).await {
+            return Introspection {
+                client_id: Some(t.client),
+                scope: Some(t.scope),
+                exp: Some((t.expires / 1000) as u64),
+                ..Introspection::active("access_token", profile)
+            };
+        }
+    }
+
+    // jwt
+    if token.matches('.').count() == 2 {
+        if let Ok(claims) = jwt::verify(database, &token).await {
+            if let Ok(profile) = database.get_profile_by_uid(claims.sub).await {
+                return Introspection {
+                    client_id: Some(claims.aud).filter(|a| !a.is_empty()),
+                    exp: Some(claims.exp),
+                    ..Introspection::active("jwt", profile)
+                };
+            }
+        }
+
+        return Introspection::default();
+    }
+
+    // account key
+    if let Ok(profile) = database
+        .get_profile_by_hashed(utility::hash(token.clone()))
+        .await
+    {
+        return Introspection::active("account", profile);
+    }
+
+    // secondary token
+    if let Ok(profile) = database.get_profile_by_unhashed_st(token).await {
+        return Introspection::active("secondary", profile);
+    }
+
+    Introspection::default()
+}
+
+/// Token introspection endpoint
+///
+/// Only confidential clients may introspect tokens. Profiles with the `Vanish`
+/// ability are not hidden from introspection.
+pub async fn introspect_request(
+    headers: HeaderMap,
+    State(database): State<Database>,
+    Form(props): Form<IntrospectFormProps>,
+) -> Response {
+    // check client
+    let client = match authenticate_client(
+        &database,
+        &headers,
+        props.client_id,
+        props.client_secret,
+    )
+    .await
+    {
+        Ok(c) => c,
+        Err(e) => return e.into_response(),
+    };
+
+    if client.secret.is_empty() {
+        return OAuthError::InvalidClient.into_response();
+    }
+
+    // return
+    (
+        [("Cache-Control", "no-store")],
+        Json(introspect(&database, props.token).await),
+    )
+        .into_response()
+}
+
+/// OpenID Connect discovery document
+pub async fn discovery_request(State(database): State<Database>) -> impl IntoResponse {
+    let issuer = database.config.issuer.trim_end_matches('/');
+
+    Json(serde_json::json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/oauth/authorize"),
+        "token_endpoint": format!("{issuer}/oauth/token"),
+        "userinfo_endpoint": format!("{issuer}/oauth/userinfo"),
+        "jwks_uri": format!("{issuer}/.well-known/jwks.json"),
+        "introspection_endpoint": format!("{issuer}/oauth/introspect"),
+        "scopes_supported": SCOPES,
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code"],
+        "subject_types_supported": ["public"],
+        "code_challenge_methods_supported": ["S256"],
+        "id_token_signing_alg_values_supported": ["EdDSA"],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+        "claims_supported": ["sub", "preferred_username", "title", "power", "defense", "abilities"],
+    }))
+}
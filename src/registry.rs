@@ -0,0 +1,382 @@
+//! Skill definitions
+//!
+//! Every skill a profile can hold is defined in a [`SkillRegistry`], which starts out
+//! with the built-in skills ([`SkillRegistry::builtin`]). Host apps can define their own
+//! skills in code:
+//!
+//! ```ignore
+//! let mut skills = SkillRegistry::default();
+//! skills.register(
+//!     SkillDefinition::new(
+//!         SkillName::Custom("Moderator".to_string()),
+//!         SkillType::Title,
+//!         500.0,
+//!         "Moderates the forum",
+//!     )
+//!     .rules(SkillRules {
+//!         requires: vec![SkillName::Protected],
+//!         ..SkillRules::default()
+//!     }),
+//! );
+//! ```
+//!
+//! Or load them from a TOML (or JSON) file with [`SkillRegistry::load`]:
+//!
+//! ```toml
+//! [[skills]]
+//! name = "Moderator"
+//! type = "Title"
+//! value = 500.0
+//! description = "Moderates the forum"
+//!
+//! [skills.rules]
+//! requires = ["Protected"]
+//! ```
+use crate::database::Result;
+use crate::model::{ProfileStats, Skill, SkillName, SkillType, StrawError};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// Rules a profile must meet before it can be granted a skill
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SkillRules {
+    /// If the skill can be granted with [`SkillManager::push`](crate::model::SkillManager::push)
+    /// (or set with [`SkillManager::title`](crate::model::SkillManager::title))
+    pub grantable: bool,
+    /// The lowest power level the profile must already have
+    pub min_power: Option<f32>,
+    /// The lowest defense level the profile must already have
+    pub min_defense: Option<f32>,
+    /// Skills the profile must already hold
+    pub requires: Vec<SkillName>,
+    /// The highest value the skill can be granted with
+    pub max_value: Option<f32>,
+}
+
+impl Default for SkillRules {
+    fn default() -> Self {
+        Self {
+            grantable: true,
+            min_power: None,
+            min_defense: None,
+            requires: Vec::new(),
+            max_value: None,
+        }
+    }
+}
+
+impl SkillRules {
+    /// Check if a profile with the given stats can be granted the skill with the given value
+    pub fn check(&self, value: f32, stats: &ProfileStats) -> bool {
+        self.grantable
+            && self.min_power.map(|p| stats.power >= p).unwrap_or(true)
+            && self.min_defense.map(|d| stats.defense >= d).unwrap_or(true)
+            && self.max_value.map(|v| value <= v).unwrap_or(true)
+            && self
+                .requires
+                .iter()
+                .all(|r| stats.skills.iter().any(|s| &s.0 .1 == r))
+    }
+}
+
+/// A skill known to a [`SkillRegistry`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SkillDefinition {
+    pub name: SkillName,
+    #[serde(rename = "type")]
+    pub skill_type: SkillType,
+    /// The value the skill is granted with unless another value is given
+    pub value: f32,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub rules: SkillRules,
+}
+
+impl SkillDefinition {
+    /// Create a new [`SkillDefinition`] with the default [`SkillRules`]
+    pub fn new(name: SkillName, skill_type: SkillType, value: f32, description: &str) -> Self {
+        Self {
+            name,
+            skill_type,
+            value,
+            description: description.to_string(),
+            rules: SkillRules::default(),
+        }
+    }
+
+    /// Replace the rules of the skill
+    pub fn rules(mut self, rules: SkillRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Get the skill with its default value
+    pub fn skill(&self) -> Skill {
+        ((self.skill_type.clone(), self.name.clone()), self.value)
+    }
+}
+
+/// A [`SkillRegistry`] file which could not be loaded
+#[derive(Clone, Debug)]
+pub struct SkillRegistryError {
+    pub error: String,
+}
+
+impl Display for SkillRegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid skill registry: {}", self.error)
+    }
+}
+
+impl std::error::Error for SkillRegistryError {}
+
+/// The layout of a skill registry file
+#[derive(Deserialize)]
+struct SkillFile {
+    #[serde(default)]
+    skills: Vec<SkillDefinition>,
+}
+
+/// Every skill which can be held by a profile
+#[derive(Clone, Debug)]
+pub struct SkillRegistry {
+    skills: Vec<SkillDefinition>,
+}
+
+impl Default for SkillRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl SkillRegistry {
+    /// Create a [`SkillRegistry`] without any skills
+    pub fn empty() -> Self {
+        Self { skills: Vec::new() }
+    }
+
+    /// Create a [`SkillRegistry`] with the built-in skills
+    pub fn builtin() -> Self {
+        use SkillName::*;
+        let mut registry = Self::empty();
+
+        for (name, description, rules) in [
+            // modifiers
+            (Master, "Doubles power", SkillRules::default()),
+            (Patron, "Doubles defense", SkillRules::default()),
+            (Trustworthy, "Multiplies power by 1.05", SkillRules::default()),
+            (Protected, "Multiplies defense by 1.05", SkillRules::default()),
+            // abilities
+            (
+                Absolute,
+                "The ability to do anything and everything",
+                SkillRules {
+                    min_power: Some(100_000.0),
+                    ..SkillRules::default()
+                },
+            ),
+            (
+                Vanish,
+                "Denies (public) spirit inspection access on the profile",
+                SkillRules::default(),
+            ),
+            // titles
+            (
+                God,
+                "Multiplies all skill levels by 100,000; allows editing the skills of other profiles",
+                SkillRules {
+                    grantable: false,
+                    ..SkillRules::default()
+                },
+            ),
+            (
+                Administrator,
+                "Multiplies all skill levels by 10,000",
+                SkillRules::default(),
+            ),
+            (
+                Manager,
+                "Multiplies all skill levels by 1,000",
+                SkillRules::default(),
+            ),
+            (Normal, "Multiplies all skill levels by 1", SkillRules::default()),
+        ] {
+            let ((skill_type, name), value): Skill = name.into();
+            registry.register(SkillDefinition::new(name, skill_type, value, description).rules(rules));
+        }
+
+        registry
+    }
+
+    /// Create a [`SkillRegistry`] with the built-in skills and the skills defined in the
+    /// given TOML
+    pub fn from_toml(input: &str) -> std::result::Result<Self, SkillRegistryError> {
+        match toml::from_str::<SkillFile>(input) {
+            Ok(f) => Ok(Self::builtin().extend(f.skills)),
+            Err(e) => Err(SkillRegistryError {
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    /// Create a [`SkillRegistry`] with the built-in skills and the skills defined in the
+    /// given JSON
+    pub fn from_json(input: &str) -> std::result::Result<Self, SkillRegistryError> {
+        match serde_json::from_str::<SkillFile>(input) {
+            Ok(f) => Ok(Self::builtin().extend(f.skills)),
+            Err(e) => Err(SkillRegistryError {
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    /// Load a [`SkillRegistry`] from a file, read as JSON if it ends with `.json` and
+    /// as TOML otherwise
+    pub fn load(path: &str) -> std::result::Result<Self, SkillRegistryError> {
+        let input = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(SkillRegistryError {
+                    error: format!("{path}: {e}"),
+                })
+            }
+        };
+
+        let registry = if path.ends_with(".json") {
+            Self::from_json(&input)
+        } else {
+            Self::from_toml(&input)
+        };
+
+        registry.map_err(|e| SkillRegistryError {
+            error: format!("{path}: {}", e.error),
+        })
+    }
+
+    fn extend(mut self, definitions: Vec<SkillDefinition>) -> Self {
+        for definition in definitions {
+            self.register(definition);
+        }
+
+        self
+    }
+
+    /// Add a [`SkillDefinition`], replacing the definition with the same name
+    pub fn register(&mut self, definition: SkillDefinition) {
+        match self.skills.iter_mut().find(|d| d.name == definition.name) {
+            Some(d) => *d = definition,
+            None => self.skills.push(definition),
+        }
+    }
+
+    /// Get the [`SkillDefinition`] of a skill
+    pub fn get(&self, name: &SkillName) -> Option<&SkillDefinition> {
+        self.skills.iter().find(|d| &d.name == name)
+    }
+
+    /// Get every [`SkillDefinition`], in the order they were registered
+    pub fn skills(&self) -> &[SkillDefinition] {
+        &self.skills
+    }
+
+    /// Get a skill with its default value, failing if it isn't registered
+    pub fn skill(&self, name: &SkillName) -> Result<Skill> {
+        match self.get(name) {
+            Some(d) => Ok(d.skill()),
+            None => Err(StrawError::ValueError),
+        }
+    }
+
+    /// Check if a profile with the given stats can be granted the given skill
+    ///
+    /// The skill must be registered with the same [`SkillType`], and meet its [`SkillRules`].
+    pub fn check(&self, skill: &Skill, stats: &ProfileStats) -> Result<()> {
+        let definition = match self.get(&skill.0 .1) {
+            Some(d) => d,
+            None => return Err(StrawError::ValueError),
+        };
+
+        if (definition.skill_type != skill.0 .0) | !definition.rules.check(skill.1, stats) {
+            return Err(StrawError::ValueError);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::SkillManager;
+
+    fn stats(skills: &[SkillName]) -> ProfileStats {
+        SkillManager::new(skills.iter().cloned().map(Into::into).collect()).get_stats()
+    }
+
+    #[test]
+    fn check_builtin_rules() {
+        let registry = SkillRegistry::builtin();
+        let normal = stats(&[SkillName::Normal]);
+
+        registry
+            .check(&SkillName::Trustworthy.into(), &normal)
+            .unwrap();
+
+        // the type must match the definition
+        assert!(registry
+            .check(&((SkillType::Title, SkillName::Trustworthy), 1.05), &normal)
+            .is_err());
+
+        // God is never grantable, Absolute needs 100,000 power
+        assert!(registry.check(&SkillName::God.into(), &normal).is_err());
+        assert!(registry
+            .check(&SkillName::Absolute.into(), &normal)
+            .is_err());
+
+        // unknown skills aren't either
+        assert!(registry
+            .check(&SkillName::Custom("Unknown".to_string()).into(), &normal)
+            .is_err());
+    }
+
+    #[test]
+    fn check_custom_rules() {
+        let registry = SkillRegistry::from_toml(
+            r#"
+            [[skills]]
+            name = "Moderator"
+            type = "Title"
+            value = 500.0
+
+            [skills.rules]
+            requires = ["Protected"]
+            min_power = 10.0
+            max_value = 500.0
+            "#,
+        )
+        .unwrap();
+
+        let skill = registry
+            .skill(&SkillName::Custom("Moderator".to_string()))
+            .unwrap();
+
+        // required skill and power
+        assert!(registry
+            .check(&skill, &stats(&[SkillName::Normal]))
+            .is_err());
+        assert!(registry
+            .check(&skill, &stats(&[SkillName::Normal, SkillName::Protected]))
+            .is_err());
+
+        let strong = stats(&[SkillName::Manager, SkillName::Protected]);
+        registry.check(&skill, &strong).unwrap();
+
+        // value cap
+        assert!(registry.check(&(skill.0, 501.0), &strong).is_err());
+
+        // built-in skills are kept
+        assert!(registry.get(&SkillName::God).is_some());
+    }
+}
@@ -0,0 +1,465 @@
+//! Storage backends
+//!
+//! Every operation on profiles, sessions, keys and OAuth grants goes through the
+//! [`ProfileStore`] trait. [`Database`](crate::Database) wraps a store, and can be
+//! created with the SQL store ([`SqlStore`](crate::database::SqlStore)) or the in-memory
+//! store ([`MemoryStore`](crate::memory::MemoryStore)).
+//!
+//! Stores record every change to a profile in the audit log ([`AuditEntry`]) along with
+//! the change itself, so a change fails if its entry can't be written.
+use crate::database::Result;
+use crate::migration::{Migration, MigrationError};
+use crate::model::{
+    Actor, ApiKey, ApiKeyScope, AuditAction, AuditEntry, AuditQuery, OAuthClient, OAuthCode,
+    OAuthToken, Profile, ProfileMetadata, SecondaryToken, Session, SigningKey, SkillGrant,
+    SkillName, SkillSet, StrawError,
+};
+use dorsal::utility;
+
+/// A place profiles and everything which belongs to them are stored
+#[async_trait::async_trait]
+pub trait ProfileStore: Send + Sync {
+    /// Apply every [`Migration`] which hasn't been applied to this store yet, in order.
+    /// Returns the `id` of each applied migration
+    ///
+    /// Stores which don't have a schema apply nothing.
+    async fn apply_migrations(
+        &self,
+        migrations: &[Migration],
+    ) -> std::result::Result<Vec<String>, MigrationError>;
+
+    /// Check that the store can be reached
+    async fn ping(&self) -> Result<()>;
+
+    /// Grant the "God" title to [`ServerOptions::owner`](crate::ServerOptions::owner) if it
+    /// has never been granted before. Returns `true` if the title was granted
+    ///
+    /// The grant is recorded by the store, so it happens exactly once even if the option
+    /// is changed later. Only call this once the owner is known to have been created by
+    /// an operator, anybody can sign up with the owner's username under
+    /// [`RegistrationPolicy::Open`](crate::RegistrationPolicy::Open).
+    async fn bootstrap_owner(&self) -> Result<bool>;
+
+    // profiles
+    /// Get the [`SkillSet`] of a [`Profile`] by its hashed ID, along with the
+    /// [`SkillGrant`] of each skill
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the profile's hashed ID
+    async fn get_skills(&self, profile: &str) -> Result<(SkillSet, Vec<SkillGrant>)>;
+
+    // GET
+    /// Get a [`Profile`] by their hashed ID
+    ///
+    /// # Arguments:
+    /// * `hashed` - `String` of the profile's hashed ID
+    async fn get_profile_by_hashed(&self, hashed: String) -> Result<Profile>;
+
+    /// Get a user by their unhashed ID (hashes ID and then calls [`ProfileStore::get_profile_by_hashed()`])
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the user's unhashed ID
+    async fn get_profile_by_unhashed(&self, unhashed: String) -> Result<Profile> {
+        match self
+            .get_profile_by_hashed(utility::hash(unhashed.clone()))
+            .await
+        {
+            Ok(r) => Ok(r),
+            Err(_) => self.get_profile_by_unhashed_st(unhashed).await,
+        }
+    }
+
+    /// Get a [`Profile`] by their public ID ([`Profile::uid`])
+    ///
+    /// # Arguments:
+    /// * `uid` - `String` of the profile's public ID
+    async fn get_profile_by_uid(&self, uid: String) -> Result<Profile>;
+
+    /// Get a user by their unhashed secondary token
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the user's unhashed secondary token
+    async fn get_profile_by_unhashed_st(&self, unhashed: String) -> Result<Profile>;
+
+    /// Get a user by their username
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    async fn get_profile_by_username(&self, username: String) -> Result<Profile>;
+
+    /// Get [`Profile`]s whose username contains the given query, ordered by username
+    ///
+    /// # Arguments:
+    /// * `query` - `String` to search usernames for, an empty query matches every profile
+    /// * `limit` - the maximum number of profiles to return
+    /// * `offset` - the number of matching profiles to skip
+    async fn get_profiles(
+        &self,
+        query: String,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Profile>>;
+
+    /// Get every [`Profile`] which holds the given skill (or title), ordered by username
+    ///
+    /// # Arguments:
+    /// * `skill` - the [`SkillName`] to look for
+    async fn get_profiles_with_skill(&self, skill: SkillName) -> Result<Vec<Profile>>;
+
+    /// Get every [`Profile`] holding a skill which expired at or before the given time
+    ///
+    /// # Arguments:
+    /// * `now` - the current unix timestamp (in milliseconds)
+    async fn get_profiles_with_expired_skills(&self, now: u128) -> Result<Vec<Profile>>;
+
+    // SET
+    /// Create a new user given their username. Returns their hashed ID
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's `username`
+    /// * `actor` - [`Actor`] creating the profile
+    async fn create_profile(&self, username: String, actor: &Actor) -> Result<String>;
+
+    /// Update a [`Profile`]'s metadata by its `username`. Returns the new [`Profile::version`]
+    ///
+    /// Fails with [`StrawError::Conflict`] if the profile's version isn't `version`.
+    async fn edit_profile_metadata_by_name(
+        &self,
+        name: String,
+        metadata: ProfileMetadata,
+        version: u64,
+        actor: &Actor,
+    ) -> Result<u64>;
+
+    /// Get the [`SecondaryToken`]s of a [`Profile`] by its hashed ID
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the profile's hashed ID
+    async fn get_secondary_tokens(&self, profile: String) -> Result<Vec<SecondaryToken>>;
+
+    /// Create a new named [`SecondaryToken`] for a [`Profile`] by its `username`. Returns the unhashed token
+    ///
+    /// # Arguments:
+    /// * `name` - `String` of the profile's username
+    /// * `label` - `String` of the token's name
+    /// * `actor` - [`Actor`] creating the token
+    async fn create_secondary_token(
+        &self,
+        name: String,
+        label: String,
+        actor: &Actor,
+    ) -> Result<String>;
+
+    /// Remove a named [`SecondaryToken`] from a [`Profile`] by its `username`
+    ///
+    /// # Arguments:
+    /// * `name` - `String` of the profile's username
+    /// * `label` - `String` of the token's name
+    /// * `actor` - [`Actor`] removing the token
+    async fn delete_secondary_token(
+        &self,
+        name: String,
+        label: String,
+        actor: &Actor,
+    ) -> Result<()>;
+
+    /// Update a [`Profile`]'s skills by its `username`. Returns the new [`Profile::version`]
+    ///
+    /// Fails with [`StrawError::Conflict`] if the profile's version isn't `version`, so
+    /// concurrent read-modify-write edits can't overwrite each other.
+    ///
+    /// # Arguments:
+    /// * `grants` - the [`SkillGrant`] of each skill in `skills`
+    /// * `actor` - [`Actor`] making the change
+    /// * `action` - [`AuditAction`] the change is recorded as
+    async fn edit_profile_skills_by_name(
+        &self,
+        name: String,
+        skills: SkillSet,
+        grants: Vec<SkillGrant>,
+        version: u64,
+        actor: &Actor,
+        action: AuditAction,
+    ) -> Result<u64>;
+
+    /// Replace a [`Profile`]'s unhashed ID by its `username`, removing all of its sessions. Returns the new unhashed ID
+    ///
+    /// API keys and secondary tokens are kept.
+    ///
+    /// # Arguments:
+    /// * `name` - `String` of the profile's username
+    /// * `actor` - [`Actor`] replacing the ID
+    async fn rotate_profile_key(&self, name: String, actor: &Actor) -> Result<String>;
+
+    /// Delete a [`Profile`] by its `username`, along with its skills, sessions, API keys,
+    /// secondary tokens, handoff codes, OAuth grants and registered OAuth clients
+    ///
+    /// # Arguments:
+    /// * `name` - `String` of the profile's username
+    /// * `actor` - [`Actor`] deleting the profile
+    async fn delete_profile_by_name(&self, name: String, actor: &Actor) -> Result<()>;
+
+    // sessions
+    // GET
+    /// Get a [`Session`] by its unhashed token
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the session's unhashed token
+    async fn get_session_by_unhashed(&self, unhashed: String) -> Result<Session>;
+
+    /// Get all [`Session`]s belonging to a profile
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the profile's hashed ID
+    async fn get_sessions_by_profile(&self, profile: String) -> Result<Vec<Session>>;
+
+    /// Get a [`Profile`] by an unhashed session token, updating the session's `last_used` time
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the session's unhashed token
+    async fn get_profile_by_session(&self, unhashed: String) -> Result<(Profile, Session)>;
+
+    // SET
+    /// Create a new [`Session`] for a profile. Returns the unhashed session token
+    ///
+    /// Recorded as a [`AuditAction::Login`] by the profile itself.
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the profile's hashed ID
+    /// * `user_agent` - `String` of the client's user agent
+    /// * `ip` - `String` of the client's IP address
+    async fn create_session(
+        &self,
+        profile: String,
+        user_agent: String,
+        ip: String,
+    ) -> Result<String>;
+
+    /// Delete a [`Session`] by its public ID
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the hashed ID of the profile which owns the session
+    /// * `id` - `String` of the session's public ID
+    /// * `actor` - [`Actor`] removing the session
+    async fn delete_session(&self, profile: String, id: String, actor: &Actor) -> Result<()>;
+
+    /// Delete every [`Session`] belonging to a profile
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the profile's hashed ID
+    /// * `actor` - [`Actor`] removing the sessions
+    async fn delete_sessions_by_profile(&self, profile: String, actor: &Actor) -> Result<()>;
+
+    // api keys
+    // GET
+    /// Get an [`ApiKey`] by its unhashed key, failing if it has expired
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the unhashed key
+    async fn get_api_key_by_unhashed(&self, unhashed: String) -> Result<ApiKey>;
+
+    /// Get all [`ApiKey`]s belonging to a profile
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the profile's hashed ID
+    async fn get_api_keys_by_profile(&self, profile: String) -> Result<Vec<ApiKey>>;
+
+    // SET
+    /// Create a new [`ApiKey`] for a profile. Returns the key and its unhashed value
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the profile's hashed ID
+    /// * `label` - `String` of the key's label
+    /// * `scope` - [`ApiKeyScope`] of the key
+    /// * `expires_in` - how long the key is valid for (in milliseconds), never expires if `None`
+    /// * `actor` - [`Actor`] creating the key
+    async fn create_api_key(
+        &self,
+        profile: String,
+        label: String,
+        scope: ApiKeyScope,
+        expires_in: Option<u128>,
+        actor: &Actor,
+    ) -> Result<(ApiKey, String)>;
+
+    /// Delete an [`ApiKey`] by its ID
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the hashed ID of the profile which owns the key
+    /// * `id` - `String` of the key's ID
+    /// * `actor` - [`Actor`] removing the key
+    async fn delete_api_key(&self, profile: String, id: String, actor: &Actor) -> Result<()>;
+
+    // handoffs
+    // SET
+    /// Create a new single-use handoff code for a profile. Returns the unhashed code
+    ///
+    /// # Arguments:
+    /// * `profile` - `String` of the profile's hashed ID
+    async fn create_handoff_code(&self, profile: String) -> Result<String>;
+
+    /// Exchange a handoff code for the hashed ID of the profile it was created for,
+    /// removing the code so it can't be used again
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the unhashed handoff code
+    async fn consume_handoff_code(&self, unhashed: String) -> Result<String>;
+
+    // oauth
+    // GET
+    /// Get an [`OAuthClient`] by its `client_id`
+    ///
+    /// # Arguments:
+    /// * `id` - `String` of the client's ID
+    async fn get_oauth_client(&self, id: String) -> Result<OAuthClient>;
+
+    /// Get an [`OAuthCode`] by its unhashed code without using it up, failing if it
+    /// has expired
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the unhashed authorization code
+    async fn get_oauth_code(&self, unhashed: String) -> Result<OAuthCode>;
+
+    /// Get an [`OAuthToken`] by its unhashed token, failing if it has expired
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the unhashed access token
+    async fn get_oauth_token_by_unhashed(&self, unhashed: String) -> Result<OAuthToken>;
+
+    // SET
+    /// Register a new [`OAuthClient`]. Returns the client and its unhashed secret (empty for public clients)
+    ///
+    /// # Arguments:
+    /// * `owner` - `String` of the hashed ID of the profile registering the client
+    /// * `name` - `String` of the client's display name
+    /// * `redirect_uris` - `Vec<String>` of the client's allowed redirect URIs
+    /// * `confidential` - `bool` of whether the client should be given a secret
+    /// * `actor` - [`Actor`] registering the client
+    async fn create_oauth_client(
+        &self,
+        owner: String,
+        name: String,
+        redirect_uris: Vec<String>,
+        confidential: bool,
+        actor: &Actor,
+    ) -> Result<(OAuthClient, String)>;
+
+    /// Create a new [`OAuthCode`], removing expired codes. Returns the unhashed code
+    ///
+    /// # Arguments:
+    /// * `code` - [`OAuthCode`] to store, its `code` and `created` fields are overwritten
+    async fn create_oauth_code(&self, code: OAuthCode) -> Result<String>;
+
+    /// Exchange an unhashed authorization code for its [`OAuthCode`], removing the code
+    /// so it can't be used again
+    ///
+    /// # Arguments:
+    /// * `unhashed` - `String` of the unhashed authorization code
+    async fn consume_oauth_code(&self, unhashed: String) -> Result<OAuthCode>;
+
+    /// Create a new [`OAuthToken`], removing expired tokens. Returns the token and its
+    /// unhashed value
+    ///
+    /// Recorded as a [`AuditAction::Login`] by the profile.
+    ///
+    /// # Arguments:
+    /// * `client` - `String` of the client's ID
+    /// * `profile` - `String` of the profile's hashed ID
+    /// * `scope` - `String` of the granted scope
+    async fn create_oauth_token(
+        &self,
+        client: String,
+        profile: String,
+        scope: String,
+    ) -> Result<(OAuthToken, String)>;
+
+    // keys
+    // GET
+    /// Get all stored [`SigningKey`]s, newest first
+    async fn get_signing_keys(&self) -> Result<Vec<SigningKey>>;
+
+    // SET
+    /// Create a new Ed25519 [`SigningKey`]
+    async fn create_signing_key(&self) -> Result<SigningKey>;
+
+    /// Replace the current [`SigningKey`] if it is older than [`ServerOptions::key_rotation_interval`](crate::ServerOptions::key_rotation_interval),
+    /// and delete keys which can no longer have signed any unexpired token
+    async fn rotate_signing_keys(&self) -> Result<()>;
+
+    // audit
+    // GET
+    /// Get the [`AuditEntry`]s matching the given [`AuditQuery`], newest first (ordered
+    /// by timestamp, then ID)
+    ///
+    /// # Arguments:
+    /// * `query` - [`AuditQuery`] of the filters, limit and offset
+    async fn get_audit_entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>>;
+
+    // SET
+    /// Append an [`AuditEntry`] to the audit log, for changes made outside of the store
+    ///
+    /// # Arguments:
+    /// * `entry` - [`AuditEntry`] to store
+    async fn create_audit_entry(&self, entry: AuditEntry) -> Result<()>;
+}
+
+// checks shared by every store
+
+/// Check that a username is allowed
+pub(crate) fn check_username(username: &str) -> Result<()> {
+    let regex = regex::RegexBuilder::new("^[\\w\\_\\-\\.\\!]+$")
+        .multi_line(true)
+        .build()
+        .unwrap();
+
+    if regex.captures(username).iter().len() < 1 {
+        return Err(StrawError::ValueError);
+    }
+
+    if (username.len() < 2) | (username.len() > 500) {
+        return Err(StrawError::ValueError);
+    }
+
+    Ok(())
+}
+
+/// Check the values of an [`OAuthClient`] which is being registered
+pub(crate) fn check_oauth_client(name: &str, redirect_uris: &[String]) -> Result<()> {
+    if name.is_empty() | (name.len() > 200) | redirect_uris.is_empty() {
+        return Err(StrawError::ValueError);
+    }
+
+    if redirect_uris
+        .iter()
+        .any(|u| !(u.starts_with("https://") | u.starts_with("http://")) | u.contains('#'))
+    {
+        return Err(StrawError::ValueError);
+    }
+
+    Ok(())
+}
+
+/// Generate a new Ed25519 [`SigningKey`]
+pub(crate) fn generate_signing_key() -> Result<SigningKey> {
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    use base64::Engine;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 = match Ed25519KeyPair::generate_pkcs8(&rng) {
+        Ok(k) => k,
+        Err(_) => return Err(StrawError::Other),
+    };
+
+    let pair = match Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()) {
+        Ok(p) => p,
+        Err(_) => return Err(StrawError::Other),
+    };
+
+    Ok(SigningKey {
+        id: utility::uuid(),
+        private: STANDARD.encode(pkcs8.as_ref()),
+        public: URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+        created: utility::unix_epoch_timestamp(),
+    })
+}
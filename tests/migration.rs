@@ -0,0 +1,105 @@
+//! The data conversions in [`starstraw::migration::builtin`], checked against sqlite
+#![cfg(feature = "sqlite")]
+use dorsal::utility;
+use starstraw::migration::{self, Migration};
+use starstraw::model::{SecondaryToken, SkillName, SkillSet};
+use starstraw::{Database, ServerOptions};
+
+/// A legacy profile row (`0001_initial`), with its skills and secondary tokens as JSON
+fn legacy_profile(
+    id: &str,
+    username: &str,
+    skills: &SkillSet,
+    tokens: &[SecondaryToken],
+) -> String {
+    let metadata = serde_json::json!({
+        "secondary_token": utility::hash(format!("legacy-{id}")),
+        "secondary_tokens": tokens,
+    });
+
+    format!(
+        "INSERT INTO \"sr_profiles\" VALUES ('{id}', '{username}', '{metadata}', '1700000000000', '{}')",
+        serde_json::to_string(skills).unwrap()
+    )
+}
+
+#[tokio::test]
+async fn sqlite_conversions() {
+    // sqlite always opens "main.db" in the working directory
+    let dir = std::env::temp_dir().join(format!("starstraw-migration-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::File::create(dir.join("main.db")).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+
+    let mut database = Database::new(
+        starstraw::DatabaseOpts {
+            _type: Some("sqlite".to_string()),
+            ..Database::env_options()
+        },
+        ServerOptions::truthy(),
+    )
+    .await;
+
+    // the schema as it was before any conversion, with two profiles sharing a name
+    let id = utility::hash("key".to_string());
+    let skills: SkillSet = vec![
+        SkillName::Normal.into(),
+        SkillName::Trustworthy.into(),
+        SkillName::Custom("Reviewer".to_string()).into(),
+    ];
+    let tokens = vec![SecondaryToken {
+        name: "ci".to_string(),
+        token: utility::hash("ci".to_string()),
+        created: 1700000000001,
+    }];
+
+    let seed = legacy_profile(&id, "legacy", &skills, &tokens);
+    let duplicate = legacy_profile("duplicate", "legacy", &skills, &[]);
+
+    database.migrations = migration::builtin()[..1].to_vec();
+    database.migrations.push(Migration::new(
+        "test_0001_seed",
+        &[seed.as_str(), duplicate.as_str()],
+    ));
+    database.migrate().await.unwrap();
+
+    // duplicate usernames stop the migrations, naming the username
+    database.migrations = migration::builtin();
+    let error = database.migrate().await.unwrap_err();
+
+    assert_eq!(error.id, "0006_profile_constraints");
+    assert!(error.error.contains("\"legacy\""));
+
+    database.migrations.insert(
+        0,
+        Migration::new(
+            "test_0002_rename",
+            &["UPDATE \"sr_profiles\" SET \"username\" = 'renamed' WHERE \"id\" = 'duplicate'"],
+        ),
+    );
+    database.migrate().await.unwrap();
+
+    // skills kept their order, both tokens still work, and every profile has a uid
+    let profile = database
+        .get_profile_by_username("legacy".to_string())
+        .await
+        .unwrap();
+
+    assert_eq!(profile.skills, skills);
+    assert!(!profile.uid.is_empty());
+
+    for token in ["ci".to_string(), format!("legacy-{id}")] {
+        assert_eq!(
+            database.get_profile_by_unhashed_st(token).await.unwrap().id,
+            id
+        );
+    }
+
+    let renamed = database
+        .get_profile_by_username("renamed".to_string())
+        .await
+        .unwrap();
+    assert_ne!(renamed.uid, profile.uid);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,283 @@
+//! The OAuth 2.0 authorization code flow (with PKCE) against an in-memory database
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use starstraw::model::Actor;
+use starstraw::{Database, ServerOptions};
+use std::collections::HashMap;
+use tower::ServiceExt;
+
+const REDIRECT_URI: &str = "https://app.example.com/callback";
+const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+struct Setup {
+    app: Router,
+    database: Database,
+    key: String,
+    client_id: String,
+}
+
+async fn setup() -> Setup {
+    let database = Database::memory(ServerOptions::truthy());
+    database.init().await.unwrap();
+
+    let key = database
+        .create_profile("alice".to_string(), &Actor::system())
+        .await
+        .unwrap();
+    let profile = database
+        .get_profile_by_username("alice".to_string())
+        .await
+        .unwrap();
+
+    // public client, so PKCE is required
+    let (client, _) = database
+        .create_oauth_client(
+            profile.id,
+            "Test <App>".to_string(),
+            vec![REDIRECT_URI.to_string()],
+            false,
+            &Actor::new("alice"),
+        )
+        .await
+        .unwrap();
+
+    Setup {
+        app: starstraw::api::routes(database.clone()),
+        database,
+        key,
+        client_id: client.id,
+    }
+}
+
+async fn send(
+    app: &Router,
+    request: Request<Body>,
+) -> (StatusCode, HashMap<String, String>, String) {
+    let res: Response = app.clone().oneshot(request).await.unwrap();
+    let status = res.status();
+
+    let headers = res
+        .headers()
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
+        .collect();
+
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn authorize_query(client_id: &str) -> String {
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(VERIFIER.as_bytes()));
+
+    serde_urlencoded::to_string([
+        ("response_type", "code"),
+        ("client_id", client_id),
+        ("redirect_uri", REDIRECT_URI),
+        ("scope", "openid profile"),
+        ("state", "xyz"),
+        ("code_challenge", &challenge),
+        ("code_challenge_method", "S256"),
+        ("nonce", "n-0S6"),
+    ])
+    .unwrap()
+}
+
+/// Get the query parameters of a redirect
+fn redirect_params(headers: &HashMap<String, String>) -> HashMap<String, String> {
+    let location = headers.get("location").expect("not a redirect");
+    assert!(location.starts_with(REDIRECT_URI));
+
+    let (_, query) = location.split_once('?').unwrap();
+    serde_urlencoded::from_str(query).unwrap()
+}
+
+/// Show the consent screen and get its consent token
+async fn consent(s: &Setup) -> String {
+    let (status, headers, body) = send(
+        &s.app,
+        Request::get(format!(
+            "/oauth/authorize?{}",
+            authorize_query(&s.client_id)
+        ))
+        .header("X-Straw-Token", &s.key)
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers.get("x-frame-options").unwrap(), "DENY");
+    assert!(body.contains("Test &lt;App&gt;"));
+
+    let (_, rest) = body.split_once("name=\"consent\" value=\"").unwrap();
+    rest.split('"').next().unwrap().to_string()
+}
+
+async fn decide(s: &Setup, consent: &str, decision: &str) -> HashMap<String, String> {
+    let (_, headers, _) = send(
+        &s.app,
+        Request::post(format!(
+            "/oauth/authorize?{}",
+            authorize_query(&s.client_id)
+        ))
+        .header("X-Straw-Token", &s.key)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(
+            serde_urlencoded::to_string([("consent", consent), ("decision", decision)]).unwrap(),
+        ))
+        .unwrap(),
+    )
+    .await;
+
+    redirect_params(&headers)
+}
+
+async fn exchange(s: &Setup, code: &str, verifier: &str) -> (StatusCode, serde_json::Value) {
+    let (status, _, body) = send(
+        &s.app,
+        Request::post("/oauth/token")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(
+                serde_urlencoded::to_string([
+                    ("grant_type", "authorization_code"),
+                    ("code", code),
+                    ("redirect_uri", REDIRECT_URI),
+                    ("client_id", &s.client_id),
+                    ("code_verifier", verifier),
+                ])
+                .unwrap(),
+            ))
+            .unwrap(),
+    )
+    .await;
+
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+#[tokio::test]
+async fn authorization_code_flow() {
+    let s = setup().await;
+
+    // authorize
+    let consent = consent(&s).await;
+    let params = decide(&s, &consent, "allow").await;
+    assert_eq!(params.get("state").unwrap(), "xyz");
+    let code = params.get("code").unwrap();
+
+    // a wrong verifier doesn't use up the code
+    let (status, body) = exchange(&s, code, "wrong-verifier").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid_grant");
+
+    let (status, token) = exchange(&s, code, VERIFIER).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(token["token_type"], "Bearer");
+    assert!(token["id_token"].is_string());
+
+    // the id token embeds the profile's stats
+    let payload = token["id_token"]
+        .as_str()
+        .unwrap()
+        .split('.')
+        .nth(1)
+        .unwrap();
+    let claims: serde_json::Value =
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+    assert_eq!(claims["title"], "Normal");
+    assert_eq!(claims["power"], 1.0);
+    assert_eq!(claims["defense"], 1.0);
+
+    // codes can only be used once
+    let (_, body) = exchange(&s, code, VERIFIER).await;
+    assert_eq!(body["error"], "invalid_grant");
+
+    // userinfo
+    let (status, _, body) = send(
+        &s.app,
+        Request::get("/oauth/userinfo")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", token["access_token"].as_str().unwrap()),
+            )
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+
+    let profile = s
+        .database
+        .get_profile_by_username("alice".to_string())
+        .await
+        .unwrap();
+
+    let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(info["sub"], profile.uid);
+    assert_eq!(info["preferred_username"], "alice");
+    assert_eq!(info["title"], "Normal");
+
+    // discovery
+    let (status, _, body) = send(
+        &s.app,
+        Request::get("/.well-known/openid-configuration")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+
+    let discovery: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(discovery["issuer"], "http://localhost:8080");
+    assert_eq!(
+        discovery["token_endpoint"],
+        "http://localhost:8080/oauth/token"
+    );
+    assert_eq!(discovery["code_challenge_methods_supported"][0], "S256");
+}
+
+#[tokio::test]
+async fn consent_is_required() {
+    let s = setup().await;
+
+    // denied
+    let consent = consent(&s).await;
+    let params = decide(&s, &consent, "deny").await;
+    assert_eq!(params.get("error").unwrap(), "access_denied");
+    assert!(!params.contains_key("code"));
+
+    // a decision without the consent token (posted by another site) is rejected
+    let params = decide(&s, "forged", "allow").await;
+    assert_eq!(params.get("error").unwrap(), "invalid_request");
+
+    // so is a token whose timestamp was changed to keep it from going stale
+    let (kid, rest) = consent.split_once('.').unwrap();
+    let (timestamp, tag) = rest.split_once('.').unwrap();
+    let timestamp: u128 = timestamp.parse().unwrap();
+    let params = decide(&s, &format!("{kid}.{}.{tag}", timestamp + 1), "allow").await;
+    assert_eq!(params.get("error").unwrap(), "invalid_request");
+
+    // not logged in
+    let (_, headers, _) = send(
+        &s.app,
+        Request::get(format!(
+            "/oauth/authorize?{}",
+            authorize_query(&s.client_id)
+        ))
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await;
+
+    assert_eq!(
+        redirect_params(&headers).get("error").unwrap(),
+        "login_required"
+    );
+}
@@ -0,0 +1,330 @@
+//! The behavior every [`ProfileStore`] has to share, checked against the in-memory store
+//! and (with the `sqlite` feature) the SQL store
+use dorsal::utility;
+use starstraw::model::{
+    Actor, ApiKeyScope, AuditAction, AuditQuery, GrantContext, ProfileMetadata, Skill, SkillGrant,
+    SkillManager, SkillName, StrawError,
+};
+use starstraw::{Database, ServerOptions};
+
+/// A username no other check uses, so every check can share one database
+fn username(name: &str) -> String {
+    format!("{name}{}", &utility::random_id()[..8]).to_lowercase()
+}
+
+/// Edits must name the version they were made against
+async fn versions(database: &Database) {
+    let name = username("versions");
+    database
+        .create_profile(name.clone(), &Actor::system())
+        .await
+        .unwrap();
+
+    let profile = database
+        .get_profile_by_username(name.clone())
+        .await
+        .unwrap();
+    let version = database
+        .edit_profile_metadata_by_name(
+            name.clone(),
+            ProfileMetadata {},
+            profile.version,
+            &Actor::system(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(version, profile.version + 1);
+
+    // the old version is stale now
+    assert!(matches!(
+        database
+            .edit_profile_skills_by_name(
+                name.clone(),
+                profile.skills.clone(),
+                profile.grants.clone(),
+                profile.version,
+                &Actor::system(),
+                AuditAction::Grant,
+            )
+            .await,
+        Err(StrawError::Conflict)
+    ));
+
+    let version = database
+        .edit_profile_skills_by_name(
+            name.clone(),
+            profile.skills,
+            profile.grants,
+            version,
+            &Actor::system(),
+            AuditAction::Grant,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        database
+            .get_profile_by_username(name)
+            .await
+            .unwrap()
+            .version,
+        version
+    );
+}
+
+/// Usernames are unique without regard to case, token names are unique per profile
+async fn uniqueness(database: &Database) {
+    let name = username("unique");
+    database
+        .create_profile(name.clone(), &Actor::system())
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        database
+            .create_profile(name.to_uppercase(), &Actor::system())
+            .await,
+        Err(StrawError::MustBeUnique)
+    ));
+
+    database
+        .create_secondary_token(name.clone(), "ci".to_string(), &Actor::system())
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        database
+            .create_secondary_token(name, "ci".to_string(), &Actor::system())
+            .await,
+        Err(StrawError::MustBeUnique)
+    ));
+}
+
+/// A new key keeps the profile, its tokens and the grants it made, but not its sessions
+async fn rotation(database: &Database) {
+    let name = username("rotation");
+    let key = database
+        .create_profile(name.clone(), &Actor::system())
+        .await
+        .unwrap();
+
+    let profile = database.get_profile_by_unhashed(key.clone()).await.unwrap();
+    let session = database
+        .create_session(profile.id.clone(), String::new(), String::new())
+        .await
+        .unwrap();
+
+    let token = database
+        .create_secondary_token(name.clone(), "ci".to_string(), &Actor::system())
+        .await
+        .unwrap();
+
+    let (_, api_key) = database
+        .create_api_key(
+            profile.id.clone(),
+            "bot".to_string(),
+            ApiKeyScope::default(),
+            None,
+            &Actor::system(),
+        )
+        .await
+        .unwrap();
+
+    // a skill granted by the rotated profile
+    let other = username("granted");
+    database
+        .create_profile(other.clone(), &Actor::system())
+        .await
+        .unwrap();
+
+    let mut granted = database
+        .get_profile_by_username(other.clone())
+        .await
+        .unwrap();
+
+    let skill: Skill = SkillName::Trustworthy.into();
+    granted.skills.push(skill);
+    granted.grants.push(SkillGrant::new(
+        SkillName::Trustworthy,
+        &GrantContext::by(&profile.id),
+    ));
+
+    database
+        .edit_profile_skills_by_name(
+            other.clone(),
+            granted.skills,
+            granted.grants,
+            0,
+            &Actor::new(&name),
+            AuditAction::Grant,
+        )
+        .await
+        .unwrap();
+
+    // rotate
+    let new_key = database
+        .rotate_profile_key(name.clone(), &Actor::system())
+        .await
+        .unwrap();
+
+    assert!(database.get_profile_by_unhashed(key).await.is_err());
+    assert!(database.get_session_by_unhashed(session).await.is_err());
+
+    let rotated = database.get_profile_by_unhashed(new_key).await.unwrap();
+    assert_eq!(rotated.uid, profile.uid);
+    assert_eq!(rotated.username, name);
+
+    assert_eq!(
+        database.get_profile_by_unhashed_st(token).await.unwrap().id,
+        rotated.id
+    );
+
+    assert_eq!(
+        database
+            .get_api_key_by_unhashed(api_key)
+            .await
+            .unwrap()
+            .profile,
+        rotated.id
+    );
+
+    let granted = database.get_profile_by_username(other).await.unwrap();
+    assert_eq!(granted.grants.last().unwrap().granted_by, rotated.id);
+}
+
+/// Expired skills are removed (and recorded), other skills are kept
+async fn expiry(database: &Database) {
+    let name = username("expiry");
+    database
+        .create_profile(name.clone(), &Actor::system())
+        .await
+        .unwrap();
+
+    let profile = database
+        .get_profile_by_username(name.clone())
+        .await
+        .unwrap();
+    let mut skills = profile.skills.clone();
+    let mut grants = profile.grants.clone();
+    let held = skills.len();
+
+    let skill: Skill = SkillName::Trustworthy.into();
+    skills.push(skill);
+    grants.push(SkillGrant::new(
+        SkillName::Trustworthy,
+        &GrantContext::default().expires_at(Some(utility::unix_epoch_timestamp() - 1)),
+    ));
+
+    database
+        .edit_profile_skills_by_name(
+            name.clone(),
+            skills,
+            grants,
+            profile.version,
+            &Actor::system(),
+            AuditAction::Grant,
+        )
+        .await
+        .unwrap();
+
+    assert!(database.expire_skills().await.unwrap() >= 1);
+
+    let profile = database
+        .get_profile_by_username(name.clone())
+        .await
+        .unwrap();
+    assert_eq!(profile.skills.len(), held);
+    assert!(!SkillManager::from(&profile).has_skill(SkillName::Trustworthy));
+
+    let entries = database
+        .get_audit_entries(&AuditQuery {
+            target: Some(name),
+            action: Some(AuditAction::Expire),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(entries.len(), 1);
+}
+
+/// Every change is recorded, and pages read with `before` don't overlap
+async fn audit(database: &Database) {
+    let name = username("audit");
+    database
+        .create_profile(name.clone(), &Actor::system())
+        .await
+        .unwrap();
+
+    for label in ["a", "b", "c", "d"] {
+        database
+            .create_secondary_token(name.clone(), label.to_string(), &Actor::new(&name))
+            .await
+            .unwrap();
+    }
+
+    let mut query = AuditQuery {
+        target: Some(name),
+        limit: 2,
+        ..Default::default()
+    };
+
+    let mut entries = Vec::new();
+
+    loop {
+        let page = database.get_audit_entries(&query).await.unwrap();
+        entries.extend(page.iter().cloned());
+
+        if page.len() < query.limit {
+            break;
+        }
+
+        query.before = page.last().map(|e| (e.timestamp, e.id.clone()));
+    }
+
+    // entries made in the same millisecond are ordered by ID
+    assert_eq!(entries.len(), 5);
+    assert!(entries.iter().any(|e| e.action == AuditAction::Register));
+
+    for pair in entries.windows(2) {
+        assert!((pair[0].timestamp, &pair[0].id) > (pair[1].timestamp, &pair[1].id));
+    }
+}
+
+async fn contract(database: Database) {
+    database.init().await.unwrap();
+
+    versions(&database).await;
+    uniqueness(&database).await;
+    rotation(&database).await;
+    expiry(&database).await;
+    audit(&database).await;
+}
+
+#[tokio::test]
+async fn memory_store() {
+    contract(Database::memory(ServerOptions::truthy())).await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn sql_store() {
+    // sqlite always opens "main.db" in the working directory
+    let dir = std::env::temp_dir().join(format!("starstraw-store-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::File::create(dir.join("main.db")).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+
+    let database = Database::new(
+        starstraw::DatabaseOpts {
+            _type: Some("sqlite".to_string()),
+            ..Database::env_options()
+        },
+        ServerOptions::truthy(),
+    )
+    .await;
+
+    contract(database).await;
+    std::fs::remove_dir_all(dir).unwrap();
+}